@@ -4,7 +4,9 @@ use std::process::Command;
 
 fn main() {
     let boot_src = "src/boot.nasm";
-    println!("cargo:rerun-if-changed={}", boot_src);
+    for src in [boot_src, "src/vbe.nasm", "src/e820.nasm"] {
+        println!("cargo:rerun-if-changed={}", src);
+    }
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let boot_obj = out_dir.join("boot.o");
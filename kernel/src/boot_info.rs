@@ -0,0 +1,279 @@
+use core::ops::Range;
+use core::ptr;
+
+use crate::paging::{self, PAGE_SIZE};
+
+pub const MAX_MEMORY_REGIONS: usize = 64;
+pub const MAX_MEMORY_RANGES: usize = 2 * MAX_MEMORY_REGIONS;
+pub const MAX_MODULES: usize = 16;
+pub const MODULE_NAME_LEN: usize = 32;
+
+static mut BOOT_INFO: BootInfo = BootInfo::new();
+
+pub fn boot_info() -> &'static BootInfo {
+    unsafe { &*ptr::addr_of!(BOOT_INFO) }
+}
+
+/// Only for the entry points, before anything else reads the boot info
+pub unsafe fn boot_info_mut() -> &'static mut BootInfo {
+    unsafe { &mut *ptr::addr_of_mut!(BOOT_INFO) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub len: u64,
+    pub kind: MemoryKind,
+}
+
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub start: usize,
+    pub end: usize,
+    name: [u8; MODULE_NAME_LEN],
+    name_len: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct FramebufferInfo {
+    pub addr: usize,
+    pub pitch: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bpp: u8,
+}
+
+/// Set of disjoint physical address ranges
+pub struct MemoryRanges {
+    ranges: [Range<usize>; MAX_MEMORY_RANGES],
+    len: usize,
+}
+
+pub struct BootInfo {
+    pub framebuffer: FramebufferInfo,
+    memory_map: [MemoryRegion; MAX_MEMORY_REGIONS],
+    memory_map_len: usize,
+    modules: [Module; MAX_MODULES],
+    modules_len: usize,
+}
+
+impl MemoryKind {
+    /// Multiboot memory map uses the same type numbers as E820
+    pub fn from_e820(kind: u32) -> Self {
+        match kind {
+            1 => Self::Usable,
+            3 => Self::AcpiReclaimable,
+            4 => Self::AcpiNvs,
+            5 => Self::BadMemory,
+            _ => Self::Reserved,
+        }
+    }
+}
+
+impl Module {
+    pub const fn empty() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            name: [0; MODULE_NAME_LEN],
+            name_len: 0,
+        }
+    }
+
+    pub fn new(start: usize, end: usize, name: &[u8]) -> Self {
+        let name_len = name.len().min(MODULE_NAME_LEN);
+        let mut module = Self {
+            start,
+            end,
+            name: [0; MODULE_NAME_LEN],
+            name_len,
+        };
+        module.name[..name_len].copy_from_slice(&name[..name_len]);
+        module
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(paging::phys_to_virt(self.start), self.len()) }
+    }
+}
+
+impl FramebufferInfo {
+    pub const fn empty() -> Self {
+        Self {
+            addr: 0,
+            pitch: 0,
+            width: 0,
+            height: 0,
+            bpp: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addr == 0
+    }
+
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+}
+
+impl MemoryRanges {
+    pub const fn new() -> Self {
+        Self {
+            ranges: [const { 0..0 }; MAX_MEMORY_RANGES],
+            len: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<usize>> {
+        self.ranges[..self.len].iter()
+    }
+
+    pub fn total(&self) -> usize {
+        self.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn insert(&mut self, range: Range<usize>) {
+        self.remove(range.clone());
+        self.push(range);
+    }
+
+    pub fn remove(&mut self, hole: Range<usize>) {
+        let mut i = 0;
+        while i < self.len {
+            let range = self.ranges[i].clone();
+            if hole.start >= range.end || range.start >= hole.end {
+                i += 1;
+                continue;
+            }
+
+            self.ranges[i] = self.ranges[self.len - 1].clone();
+            self.len -= 1;
+            self.push(range.start..hole.start.max(range.start));
+            self.push(hole.end.min(range.end)..range.end);
+        }
+    }
+
+    fn push(&mut self, range: Range<usize>) {
+        if !range.is_empty() && self.len < MAX_MEMORY_RANGES {
+            self.ranges[self.len] = range;
+            self.len += 1;
+        }
+    }
+}
+
+impl BootInfo {
+    pub const fn new() -> Self {
+        Self {
+            framebuffer: FramebufferInfo::empty(),
+            memory_map: [MemoryRegion {
+                start: 0,
+                len: 0,
+                kind: MemoryKind::Reserved,
+            }; MAX_MEMORY_REGIONS],
+            memory_map_len: 0,
+            modules: [Module::empty(); MAX_MODULES],
+            modules_len: 0,
+        }
+    }
+
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        &self.memory_map[..self.memory_map_len]
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.modules_len]
+    }
+
+    pub fn module(&self, name: &[u8]) -> Option<&Module> {
+        self.modules().iter().find(|module| module.name() == name)
+    }
+
+    pub fn push_memory_region(&mut self, region: MemoryRegion) {
+        if self.memory_map_len < MAX_MEMORY_REGIONS {
+            self.memory_map[self.memory_map_len] = region;
+            self.memory_map_len += 1;
+        }
+    }
+
+    pub fn push_module(&mut self, module: Module) {
+        if self.modules_len < MAX_MODULES {
+            self.modules[self.modules_len] = module;
+            self.modules_len += 1;
+        }
+    }
+
+    /// Usable memory from the memory map above `start`, page aligned and
+    /// without the framebuffer and modules
+    pub fn free_memory(&self, start: usize) -> MemoryRanges {
+        let page_down = |addr: u64| addr.min(usize::MAX as u64) as usize & !(PAGE_SIZE - 1);
+        let page_up = |addr: u64| page_down(addr + PAGE_SIZE as u64 - 1);
+
+        let mut ranges = MemoryRanges::new();
+        let regions = self.memory_map().iter();
+        for region in regions.clone().filter(|r| r.kind == MemoryKind::Usable) {
+            ranges.insert(page_up(region.start)..page_down(region.start + region.len));
+        }
+        // regions may overlap, the reserved ones win
+        for region in regions.filter(|r| r.kind != MemoryKind::Usable) {
+            ranges.remove(page_down(region.start)..page_up(region.start + region.len));
+        }
+
+        ranges.remove(0..start);
+        ranges.remove(self.framebuffer.addr..self.framebuffer.addr + self.framebuffer.size());
+        for module in self.modules() {
+            ranges.remove(module.start..module.end);
+        }
+        ranges
+    }
+
+    /// Moves every module that overlaps the physical `range` to usable memory
+    /// above it. Bootloaders are free to put modules anywhere, including the
+    /// kernel heap.
+    pub fn evacuate_modules(&mut self, range: Range<usize>) {
+        for i in 0..self.modules_len {
+            let module = self.modules[i];
+            if module.end <= range.start || range.end <= module.start {
+                continue;
+            }
+
+            // the copy is made through the direct map, and must not hit the
+            // heap or the other modules
+            let mut free = self.free_memory(range.end);
+            free.remove(paging::DIRECT_MAP_SIZE..usize::MAX);
+            let len = module.len();
+            let dest = free
+                .iter()
+                .find(|free| free.len() >= len)
+                .expect("No free memory to move a boot module out of the kernel heap")
+                .start;
+
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    paging::phys_to_virt::<u8>(module.start),
+                    paging::phys_to_virt(dest),
+                    len,
+                );
+            }
+            self.modules[i].start = dest;
+            self.modules[i].end = dest + len;
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use crate::x86_utils::{EFlags, cli, sti};
 
-pub fn wrap(f: impl FnOnce()) {
+pub fn wrap<T>(f: impl FnOnce() -> T) -> T {
     let flag = EFlags::read().contains(EFlags::IF);
     if flag {
         unsafe { cli() }
     }
-    f();
+    let res = f();
     if flag {
         unsafe { sti() }
     }
+    res
 }
@@ -1,20 +1,57 @@
 use crate::{
     TBW,
     drivers::{
+        ata::AtaBus,
+        bga::{self, Bga},
+        block::BlockDevice,
+        pci::{self, PciDevice, PciDriver, PciMatch},
         pic8259,
         pit::{self},
         ps2,
+        rtc::{self, RtcInterrupts},
+        uart::{self, Uart},
     },
-    interrupts,
+    interrupts, time,
 };
-use utils::io::Write;
+use alloc::{boxed::Box, vec::Vec};
+use utils::{io::Write, nullsync};
 
 pub static DEVICES: DeviceManager = DeviceManager::new();
 
+const SERIAL_BAUD: u32 = 115_200;
+
+/// Drivers of PCI devices, the first one that matches and takes a device owns it
+const PCI_DRIVERS: &[PciDriver] = &[
+    PciDriver {
+        name: "ata",
+        matches: &[PciMatch::Class {
+            class: 0x01,
+            subclass: 0x01,
+        }],
+        probe: |dev| DEVICES.init_ata(dev),
+    },
+    PciDriver {
+        name: "bga",
+        matches: bga::PCI_IDS,
+        // the mode is set before the kernel starts
+        probe: |_| Bga::new().is_present(),
+    },
+];
+
+/// IDE programming interface bits, the channel uses its own BARs and IRQ
+const IDE_PRIMARY_NATIVE: u8 = 1 << 0;
+const IDE_SECONDARY_NATIVE: u8 = 1 << 2;
+
 pub struct DeviceManager {
     pub pic: pic8259::ChainedPics,
     pub ps2keyboard: ps2::PS2Keyboard,
     pub pit: pit::Pit,
+    pub rtc: rtc::Rtc,
+    pub com1: Uart,
+    pub com2: Uart,
+    /// Primary and secondary ATA channel
+    pub ata: [AtaBus; 2],
+    disks: nullsync::RefCell<Vec<&'static dyn BlockDevice>>,
 }
 
 impl DeviceManager {
@@ -23,10 +60,22 @@ impl DeviceManager {
             pic: pic8259::ChainedPics::new(0x20, 0x28),
             ps2keyboard: ps2::PS2Keyboard::new(),
             pit: pit::Pit::new(),
+            rtc: rtc::Rtc::new(),
+            com1: Uart::new(uart::COM1),
+            com2: Uart::new(uart::COM2),
+            ata: [AtaBus::new(0x1f0, 0x3f6), AtaBus::new(0x170, 0x376)],
+            disks: nullsync::RefCell::new(Vec::new()),
         }
     }
 
-    pub fn init_devices(&self) {
+    /// Sets the serial ports up for polling, so the kernel log is mirrored
+    /// to COM1 from the start
+    pub fn init_serial(&self) {
+        self.com1.init(SERIAL_BAUD);
+        self.com2.init(SERIAL_BAUD);
+    }
+
+    pub fn init_devices(&'static self) {
         TBW.borrow_mut().set_next_fg(0x00ffff00);
         crate::println!("{:=^80}", "DEVICES");
         TBW.borrow_mut().set_next_fg(0x00ffffff);
@@ -39,11 +88,92 @@ impl DeviceManager {
         self.pic.enable_device(1);
         crate::info!("PS2 controller initializated");
 
-        self.pit.init(10000);
+        self.pit.init(time::TICK_HZ);
         crate::info!("PIT initializated");
 
+        interrupts::register_handler(0x24, |_| DEVICES.com1.handle_interrupt());
+        interrupts::register_handler(0x23, |_| DEVICES.com2.handle_interrupt());
+        for (name, com, irq) in [("COM1", &self.com1, 4), ("COM2", &self.com2, 3)] {
+            if com.is_present() {
+                com.enable_interrupts();
+                self.pic.enable_device(irq);
+                crate::info!("{} initializated", name);
+            }
+        }
+
+        self.rtc.init();
+        interrupts::register_handler(0x28, rtc::Rtc::int_handler);
+        self.rtc.enable_interrupts(RtcInterrupts::UPDATE);
+        // IRQ 8 reaches the master through the cascade on IRQ 2
+        self.pic.enable_device(2);
+        self.pic.enable_device(8);
+        let now = self.rtc.read();
+        crate::info!(
+            "RTC initializated, {}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            now.year,
+            now.month,
+            now.day,
+            now.hour,
+            now.minute,
+            now.second
+        );
+
+        crate::info!("PCI devices:");
+        for dev in pci::devices() {
+            crate::println!(
+                "  {} {} [{:04x}:{:04x}] (rev {:02x})",
+                dev.address,
+                dev.class_name(),
+                dev.vendor_id,
+                dev.device_id,
+                dev.revision
+            );
+            if let Some(driver) = pci::bind(&dev, PCI_DRIVERS) {
+                crate::info!("{} bound to {}", driver, dev.address);
+            }
+        }
+
         TBW.borrow_mut().set_next_fg(0x00ffff00);
         crate::println!("{:=^80}", "");
         TBW.borrow_mut().set_next_fg(0x00ffffff);
     }
+
+    /// Probes the drives of an IDE controller in compatibility mode, where
+    /// the channels are at the ISA ports with IRQ 14 and 15
+    fn init_ata(&'static self, dev: &PciDevice) -> bool {
+        if dev.prog_if & (IDE_PRIMARY_NATIVE | IDE_SECONDARY_NATIVE) != 0 {
+            return false;
+        }
+
+        interrupts::register_handler(0x2e, |_| DEVICES.ata[0].handle_interrupt());
+        interrupts::register_handler(0x2f, |_| DEVICES.ata[1].handle_interrupt());
+        for (bus, name, irq) in [
+            (&self.ata[0], "ATA primary", 14),
+            (&self.ata[1], "ATA secondary", 15),
+        ] {
+            let drives = bus.probe(name);
+            bus.enable_interrupts();
+            self.pic.enable_device(irq);
+
+            for drive in drives.into_iter().flatten() {
+                crate::info!(
+                    "{}: {}, {} KiB",
+                    drive.name(),
+                    drive.model,
+                    drive.block_count() * drive.block_size() as u64 / 1024
+                );
+                self.add_disk(Box::leak(Box::new(drive)));
+            }
+        }
+        true
+    }
+
+    pub fn add_disk(&self, disk: &'static dyn BlockDevice) {
+        self.disks.borrow_mut().push(disk);
+    }
+
+    /// Block devices in the order they were found
+    pub fn disk(&self, index: usize) -> Option<&'static dyn BlockDevice> {
+        self.disks.borrow_mut().get(index).copied()
+    }
 }
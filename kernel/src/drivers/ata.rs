@@ -0,0 +1,374 @@
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{
+    block::{BlockDevice, BlockError},
+    port::Port,
+};
+use crate::{critical_section, process};
+use bitflags::bitflags;
+
+pub const SECTOR_SIZE: usize = 512;
+/// Sectors transferred by one command
+const MAX_SECTORS: usize = 128;
+const LBA28_LIMIT: u64 = 1 << 28;
+
+// registers relative to the I/O base
+const DATA: u16 = 0;
+const ERROR: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE: u16 = 6;
+const COMMAND: u16 = 7;
+const STATUS: u16 = 7;
+
+const IDENTIFY: u8 = 0xec;
+const READ_SECTORS: u8 = 0x20;
+const READ_SECTORS_EXT: u8 = 0x24;
+const WRITE_SECTORS: u8 = 0x30;
+const WRITE_SECTORS_EXT: u8 = 0x34;
+const CACHE_FLUSH: u8 = 0xe7;
+const CACHE_FLUSH_EXT: u8 = 0xea;
+
+/// Drive register, LBA addressing instead of CHS
+const DRIVE_LBA: u8 = 0xe0;
+const DRIVE_SLAVE: u8 = 1 << 4;
+/// Device control register, interrupts off
+const CONTROL_NIEN: u8 = 1 << 1;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct Status: u8 {
+        const ERR = 1 << 0;
+        const DRQ = 1 << 3;
+        const DF = 1 << 5;
+        const RDY = 1 << 6;
+        const BSY = 1 << 7;
+    }
+}
+
+/// ATA channel with up to two drives, commands on it run one at a time
+pub struct AtaBus {
+    io: u16,
+    control: u16,
+    /// An interrupt arrived since the last command was issued
+    irq: AtomicBool,
+    /// The IRQ handler is registered, waiting processes can sleep
+    interrupts: AtomicBool,
+    busy: AtomicBool,
+    /// Processes waiting for the interrupt or for the bus
+    waiters: process::WaitQueue,
+}
+
+/// Drive found by IDENTIFY, read and written with PIO
+pub struct AtaDrive {
+    bus: &'static AtaBus,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+    name: String,
+    pub model: String,
+}
+
+impl AtaBus {
+    pub const fn new(io: u16, control: u16) -> Self {
+        Self {
+            io,
+            control,
+            irq: AtomicBool::new(false),
+            interrupts: AtomicBool::new(false),
+            busy: AtomicBool::new(false),
+            waiters: process::WaitQueue::new(),
+        }
+    }
+
+    /// Runs IDENTIFY on both drive positions
+    pub fn probe(&'static self, name: &str) -> [Option<AtaDrive>; 2] {
+        // nothing drives the lines of a missing bus
+        if self.port(STATUS).read() == 0xff {
+            return [None, None];
+        }
+        [false, true].map(|slave| self.identify(name, slave))
+    }
+
+    /// Makes the commands complete on the IRQ, the handler must be registered
+    pub fn enable_interrupts(&self) {
+        self.interrupts.store(true, Ordering::Relaxed);
+        Port::<u8>::new(self.control).write(0);
+    }
+
+    /// IRQ 14 or 15
+    pub fn handle_interrupt(&self) {
+        // reading the status acknowledges the interrupt
+        self.port(STATUS).read();
+        self.irq.store(true, Ordering::Relaxed);
+        self.waiters.wake_all();
+    }
+
+    fn identify(&'static self, bus_name: &str, slave: bool) -> Option<AtaDrive> {
+        self.lock();
+        let drive = critical_section::wrap(|| {
+            Port::<u8>::new(self.control).write(CONTROL_NIEN);
+            self.select(slave, 0);
+            for reg in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH] {
+                self.port(reg).write(0);
+            }
+            self.port(COMMAND).write(IDENTIFY);
+            if self.port(STATUS).read() == 0 {
+                return None;
+            }
+
+            while self.status().contains(Status::BSY) {}
+            // ATAPI and SATA devices set the signature in these registers
+            if self.port(LBA_MID).read() != 0 || self.port(LBA_HIGH).read() != 0 {
+                return None;
+            }
+            loop {
+                let status = self.status();
+                if status.intersects(Status::ERR | Status::DF) {
+                    return None;
+                }
+                if status.contains(Status::DRQ) {
+                    break;
+                }
+            }
+
+            let mut words = [0u16; 256];
+            for word in &mut words {
+                *word = Port::<u16>::new(self.io + DATA).read();
+            }
+            Some(words)
+        });
+        if self.interrupts.load(Ordering::Relaxed) {
+            Port::<u8>::new(self.control).write(0);
+        }
+        self.unlock();
+
+        let words = drive?;
+        let lba48 = words[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            words[100..104]
+                .iter()
+                .rev()
+                .fold(0, |sectors, &word| sectors << 16 | word as u64)
+        } else {
+            (words[61] as u64) << 16 | words[60] as u64
+        };
+        // the model is space padded ASCII with the bytes of each word swapped
+        let model = words[27..47]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(char::from)
+            .collect::<String>();
+
+        Some(AtaDrive {
+            bus: self,
+            slave,
+            lba48,
+            sectors,
+            name: alloc::format!("{} {}", bus_name, if slave { "slave" } else { "master" }),
+            model: String::from(model.trim_end()),
+        })
+    }
+
+    /// Selects the drive, with the top bits of an LBA28 address
+    fn select(&self, slave: bool, lba_high: u8) {
+        let slave = if slave { DRIVE_SLAVE } else { 0 };
+        self.port(DRIVE)
+            .write(DRIVE_LBA | slave | (lba_high & 0x0f));
+        // the drive needs 400ns to put its status on the bus
+        for _ in 0..4 {
+            Port::<u8>::new(self.control).read();
+        }
+    }
+
+    /// Sends a read or write command for `count` sectors at `lba`
+    fn command(&self, drive: &AtaDrive, lba: u64, count: usize, lba28: u8, lba48: u8) {
+        self.irq.store(false, Ordering::Relaxed);
+        if drive.lba48 {
+            self.select(drive.slave, 0);
+            // high bytes first, the registers are two deep
+            self.port(SECTOR_COUNT).write((count >> 8) as u8);
+            self.port(LBA_LOW).write((lba >> 24) as u8);
+            self.port(LBA_MID).write((lba >> 32) as u8);
+            self.port(LBA_HIGH).write((lba >> 40) as u8);
+            self.port(SECTOR_COUNT).write(count as u8);
+            self.port(LBA_LOW).write(lba as u8);
+            self.port(LBA_MID).write((lba >> 8) as u8);
+            self.port(LBA_HIGH).write((lba >> 16) as u8);
+            self.port(COMMAND).write(lba48);
+        } else {
+            self.select(drive.slave, (lba >> 24) as u8);
+            self.port(SECTOR_COUNT).write(count as u8);
+            self.port(LBA_LOW).write(lba as u8);
+            self.port(LBA_MID).write((lba >> 8) as u8);
+            self.port(LBA_HIGH).write((lba >> 16) as u8);
+            self.port(COMMAND).write(lba28);
+        }
+    }
+
+    /// Waits until the drive is done with the last step, on the IRQ if a
+    /// process can sleep and by polling otherwise
+    fn wait(&self) -> Result<Status, BlockError> {
+        if self.can_sleep() {
+            while !self.irq.swap(false, Ordering::Relaxed) {
+                self.waiters.sleep();
+            }
+        }
+
+        let mut status = self.status();
+        while status.contains(Status::BSY) {
+            status = self.status();
+        }
+        if status.intersects(Status::ERR | Status::DF) {
+            // the error register tells why, nobody needs to know yet
+            self.port(ERROR).read();
+            return Err(BlockError::Io);
+        }
+        Ok(status)
+    }
+
+    /// Waits until the drive takes or offers the data of a sector
+    fn wait_drq(&self) -> Result<(), BlockError> {
+        loop {
+            let status = self.status();
+            if status.intersects(Status::ERR | Status::DF) {
+                return Err(BlockError::Io);
+            }
+            if !status.contains(Status::BSY) && status.contains(Status::DRQ) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn can_sleep(&self) -> bool {
+        self.interrupts.load(Ordering::Relaxed) && process::processes().current_pid().is_some()
+    }
+
+    fn lock(&self) {
+        while self.busy.swap(true, Ordering::Acquire) {
+            debug_assert!(self.can_sleep(), "ATA bus in use without a process");
+            self.waiters.sleep();
+        }
+    }
+
+    fn unlock(&self) {
+        self.busy.store(false, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Alternate status, reading it doesn't acknowledge the interrupt
+    fn status(&self) -> Status {
+        Status::from_bits_retain(Port::<u8>::new(self.control).read())
+    }
+
+    const fn port(&self, offset: u16) -> Port<u8> {
+        Port::new(self.io + offset)
+    }
+}
+
+impl AtaDrive {
+    fn read_chunk(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let bus = self.bus;
+        bus.command(
+            self,
+            lba,
+            buf.len() / SECTOR_SIZE,
+            READ_SECTORS,
+            READ_SECTORS_EXT,
+        );
+
+        for sector in buf.chunks_exact_mut(SECTOR_SIZE) {
+            bus.wait()?;
+            bus.wait_drq()?;
+            for word in sector.chunks_exact_mut(2) {
+                word.copy_from_slice(&Port::<u16>::new(bus.io + DATA).read().to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        let bus = self.bus;
+        bus.command(
+            self,
+            lba,
+            buf.len() / SECTOR_SIZE,
+            WRITE_SECTORS,
+            WRITE_SECTORS_EXT,
+        );
+
+        for sector in buf.chunks_exact(SECTOR_SIZE) {
+            bus.wait_drq()?;
+            for word in sector.chunks_exact(2) {
+                Port::<u16>::new(bus.io + DATA).write(u16::from_le_bytes([word[0], word[1]]));
+            }
+            bus.wait()?;
+        }
+
+        bus.irq.store(false, Ordering::Relaxed);
+        bus.port(COMMAND).write(if self.lba48 {
+            CACHE_FLUSH_EXT
+        } else {
+            CACHE_FLUSH
+        });
+        bus.wait()?;
+        Ok(())
+    }
+
+    /// Runs `f` on the chunks of `len` bytes one command can transfer, with
+    /// the bus locked
+    fn transfer(
+        &self,
+        start: u64,
+        len: usize,
+        mut f: impl FnMut(u64, core::ops::Range<usize>) -> Result<(), BlockError>,
+    ) -> Result<(), BlockError> {
+        self.check_range(start, len)?;
+
+        self.bus.lock();
+        let mut result = Ok(());
+        for (i, offset) in (0..len).step_by(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let lba = start + (i * MAX_SECTORS) as u64;
+            let end = len.min(offset + MAX_SECTORS * SECTOR_SIZE);
+            result = f(lba, offset..end);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.bus.unlock();
+        result
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        if self.lba48 {
+            self.sectors
+        } else {
+            self.sectors.min(LBA28_LIMIT)
+        }
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.transfer(start, buf.len(), |lba, range| {
+            self.read_chunk(lba, &mut buf[range])
+        })
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.transfer(start, buf.len(), |lba, range| {
+            self.write_chunk(lba, &buf[range])
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use crate::boot_info::FramebufferInfo;
+
+use super::{
+    pci::{self, Bar, PciMatch},
+    port::Port,
+};
+
+const INDEX: Port<u16> = Port::new(0x1ce);
+const DATA: Port<u16> = Port::new(0x1cf);
+
+const INDEX_ID: u16 = 0;
+const INDEX_XRES: u16 = 1;
+const INDEX_YRES: u16 = 2;
+const INDEX_BPP: u16 = 3;
+const INDEX_ENABLE: u16 = 4;
+
+const ID_MIN: u16 = 0xb0c0;
+const ID_MAX: u16 = 0xb0cf;
+
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+/// QEMU and VirtualBox display adapters
+pub const PCI_IDS: &[PciMatch] = &[
+    PciMatch::Id {
+        vendor: 0x1234,
+        device: 0x1111,
+    },
+    PciMatch::Id {
+        vendor: 0x80ee,
+        device: 0xbeef,
+    },
+];
+
+/// Bochs/QEMU display adapter. Used when the bootloader did not set a video
+/// mode, e.g. `qemu -kernel` ignores the Multiboot video mode request.
+pub struct Bga;
+
+impl Bga {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    pub fn is_present(&self) -> bool {
+        (ID_MIN..=ID_MAX).contains(&self.read(INDEX_ID))
+    }
+
+    pub fn set_mode(&self, width: u16, height: u16, bpp: u16) -> Option<FramebufferInfo> {
+        if !self.is_present() {
+            return None;
+        }
+        let addr = find_lfb()?;
+
+        self.write(INDEX_ENABLE, 0);
+        self.write(INDEX_XRES, width);
+        self.write(INDEX_YRES, height);
+        self.write(INDEX_BPP, bpp);
+        self.write(INDEX_ENABLE, ENABLED | LFB_ENABLED);
+
+        Some(FramebufferInfo {
+            addr,
+            pitch: width as usize * (bpp as usize / 8),
+            width: width as _,
+            height: height as _,
+            bpp: bpp as _,
+        })
+    }
+
+    fn read(&self, index: u16) -> u16 {
+        INDEX.write(index);
+        DATA.read()
+    }
+
+    fn write(&self, index: u16, value: u16) {
+        INDEX.write(index);
+        DATA.write(value);
+    }
+}
+
+/// The linear framebuffer is BAR0 of the PCI device
+fn find_lfb() -> Option<usize> {
+    pci::devices()
+        .find(|dev| PCI_IDS.iter().any(|id| dev.matches(id)))
+        .and_then(|dev| match dev.bar(0)? {
+            Bar::Memory { addr, .. } => Some(addr as usize),
+            Bar::Io { .. } => None,
+        })
+}
@@ -0,0 +1,109 @@
+use alloc::{format, string::String, vec, vec::Vec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The blocks are past the end of the device
+    OutOfRange,
+    /// The buffer is not a whole number of blocks
+    BadBuffer,
+    /// The device reported an error
+    Io,
+}
+
+/// Storage addressed in fixed size blocks
+pub trait BlockDevice {
+    fn name(&self) -> &str;
+
+    /// Size of a block in bytes
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks on the device
+    fn block_count(&self) -> u64;
+
+    /// Fills `buf` with the blocks starting at `start`, its length must be a
+    /// multiple of the block size
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf` to the blocks starting at `start`, its length must be a
+    /// multiple of the block size
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Checks that `len` bytes fit at block `start`, returns the number of blocks
+    fn check_range(&self, start: u64, len: usize) -> Result<u64, BlockError> {
+        if !len.is_multiple_of(self.block_size()) {
+            return Err(BlockError::BadBuffer);
+        }
+        let count = (len / self.block_size()) as u64;
+        match start.checked_add(count) {
+            Some(end) if end <= self.block_count() => Ok(count),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+/// Partition table entries of an MBR, partitions of type 0 are unused
+const MBR_ENTRIES: usize = 446;
+const MBR_SIGNATURE: usize = 510;
+
+/// Blocks `start..start + count` of a disk, from its partition table
+pub struct Partition {
+    disk: &'static dyn BlockDevice,
+    start: u64,
+    count: u64,
+    name: String,
+    /// Entry in the partition table, from 1
+    pub number: usize,
+}
+
+impl Partition {
+    /// Partitions in the MBR of `disk`. The boot sector of a disk without
+    /// one may look like it, the partitions are checked against the size of
+    /// the disk but their contents may still be garbage.
+    pub fn read_mbr(disk: &'static dyn BlockDevice) -> Result<Vec<Partition>, BlockError> {
+        let mut mbr = vec![0; disk.block_size().max(512)];
+        disk.read_blocks(0, &mut mbr)?;
+        if mbr[MBR_SIGNATURE..MBR_SIGNATURE + 2] != [0x55, 0xaa] {
+            return Ok(Vec::new());
+        }
+
+        let partitions = mbr[MBR_ENTRIES..MBR_SIGNATURE]
+            .chunks_exact(16)
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry[0], 0x00 | 0x80) && entry[4] != 0)
+            .map(|(i, entry)| Partition {
+                disk,
+                start: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+                count: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+                name: format!("{} p{}", disk.name(), i + 1),
+                number: i + 1,
+            })
+            .filter(|part| part.start > 0 && part.count > 0)
+            .filter(|part| part.start.saturating_add(part.count) <= disk.block_count())
+            .collect();
+        Ok(partitions)
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.count
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(start, buf.len())?;
+        self.disk.read_blocks(self.start + start, buf)
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(start, buf.len())?;
+        self.disk.write_blocks(self.start + start, buf)
+    }
+}
@@ -1,4 +1,10 @@
+pub mod ata;
+pub mod bga;
+pub mod block;
+pub mod pci;
 pub mod pic8259;
 pub mod pit;
 pub mod port;
 pub mod ps2;
+pub mod rtc;
+pub mod uart;
@@ -0,0 +1,233 @@
+use core::fmt;
+
+use super::port::Port;
+
+const CONFIG_ADDRESS: Port<u32> = Port::new(0xcf8);
+const CONFIG_DATA: Port<u32> = Port::new(0xcfc);
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+// configuration space offsets
+const VENDOR_DEVICE: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const CLASS: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0c;
+const BAR0: u8 = 0x10;
+
+const NO_VENDOR: u16 = 0xffff;
+/// Header type bit of devices with more than one function
+const MULTI_FUNCTION: u8 = 0x80;
+/// Command register, the device answers I/O and memory accesses
+const COMMAND_DECODE: u32 = 0b11;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_64BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Position of a function on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+}
+
+/// Decoded base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        addr: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+    Io {
+        port: u16,
+        size: u32,
+    },
+}
+
+/// What a driver binds to
+#[derive(Debug, Clone, Copy)]
+pub enum PciMatch {
+    Id { vendor: u16, device: u16 },
+    Class { class: u8, subclass: u8 },
+}
+
+pub struct PciDriver {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Sets the device up, returns false if it can not be driven
+    pub probe: fn(&PciDevice) -> bool,
+}
+
+impl PciAddress {
+    pub fn read(&self, offset: u8) -> u32 {
+        CONFIG_ADDRESS.write(self.config_address(offset));
+        CONFIG_DATA.read()
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        CONFIG_ADDRESS.write(self.config_address(offset));
+        CONFIG_DATA.write(value);
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        debug_assert!(offset.is_multiple_of(4));
+        CONFIG_ENABLE
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | offset as u32
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+impl PciDevice {
+    /// Reads the identification of the function at `address`, if there is one
+    pub fn at(address: PciAddress) -> Option<Self> {
+        let ids = address.read(VENDOR_DEVICE);
+        if ids as u16 == NO_VENDOR {
+            return None;
+        }
+        let class = address.read(CLASS);
+
+        Some(Self {
+            address,
+            vendor_id: ids as u16,
+            device_id: (ids >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+        })
+    }
+
+    pub fn matches(&self, pattern: &PciMatch) -> bool {
+        match *pattern {
+            PciMatch::Id { vendor, device } => self.vendor_id == vendor && self.device_id == device,
+            PciMatch::Class { class, subclass } => self.class == class && self.subclass == subclass,
+        }
+    }
+
+    /// Base address register `index`, None if it is unused. The size is
+    /// measured by writing all ones, decoding is off meanwhile.
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        debug_assert!(index < 6);
+        let offset = BAR0 + index * 4;
+        let addr = self.address;
+
+        let command = addr.read(COMMAND);
+        addr.write(COMMAND, command & !COMMAND_DECODE);
+        let low = addr.read(offset);
+        addr.write(offset, u32::MAX);
+        let low_mask = addr.read(offset);
+        addr.write(offset, low);
+
+        let is_64bit = low & BAR_IO == 0 && low & 0b110 == BAR_64BIT;
+        let (high, high_mask) = if is_64bit && index < 5 {
+            let high = addr.read(offset + 4);
+            addr.write(offset + 4, u32::MAX);
+            let high_mask = addr.read(offset + 4);
+            addr.write(offset + 4, high);
+            (high, high_mask)
+        } else {
+            (0, 0)
+        };
+        addr.write(COMMAND, command);
+
+        if low_mask == 0 {
+            return None;
+        }
+        if low & BAR_IO != 0 {
+            // the upper half of I/O BARs may read back as zeroes
+            let mask = low_mask & !0b11 | 0xffff_0000;
+            return Some(Bar::Io {
+                port: (low & !0b11) as u16,
+                size: (!mask).wrapping_add(1),
+            });
+        }
+
+        let mask = (high_mask as u64) << 32 | (low_mask & !0xf) as u64;
+        let mask = if is_64bit {
+            mask
+        } else {
+            mask | 0xffff_ffff << 32
+        };
+        Some(Bar::Memory {
+            addr: (high as u64) << 32 | (low & !0xf) as u64,
+            size: (!mask).wrapping_add(1),
+            prefetchable: low & BAR_PREFETCHABLE != 0,
+        })
+    }
+
+    /// Name of the class for listings
+    pub fn class_name(&self) -> &'static str {
+        match (self.class, self.subclass) {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, _) => "Storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, 0x00) => "VGA controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia controller",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x0c, 0x03) => "USB controller",
+            (0x0c, 0x05) => "SMBus",
+            (0x0c, _) => "Serial bus controller",
+            _ => "Unknown device",
+        }
+    }
+}
+
+/// Every function on every bus, found by reading all of the configuration space
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255u8).flat_map(|bus| {
+        (0..32u8).flat_map(move |device| {
+            let first = PciAddress {
+                bus,
+                device,
+                function: 0,
+            };
+            let functions = match PciDevice::at(first) {
+                None => 0,
+                Some(_) if first.read(HEADER_TYPE) >> 16 & MULTI_FUNCTION as u32 != 0 => 8,
+                Some(_) => 1,
+            };
+            (0..functions).filter_map(move |function| {
+                PciDevice::at(PciAddress {
+                    bus,
+                    device,
+                    function,
+                })
+            })
+        })
+    })
+}
+
+/// First driver in `drivers` that matches `device` and takes it
+pub fn bind(device: &PciDevice, drivers: &[PciDriver]) -> Option<&'static str> {
+    drivers
+        .iter()
+        .filter(|driver| driver.matches.iter().any(|pattern| device.matches(pattern)))
+        .find(|driver| (driver.probe)(device))
+        .map(|driver| driver.name)
+}
@@ -8,8 +8,15 @@ pub const MIN_FREQ: u32 = MAX_FREQ.div_ceil(MAX_DIVISOR);
 pub const MAX_FREQ: u32 = 1193182;
 
 const CH0: Port<u8> = Port::new(0x40);
+const CH2: Port<u8> = Port::new(0x42);
 const CONTROL: Port<u8> = Port::new(0x43);
 
+/// Channel 2 gate in bit 0, PC speaker enable in bit 1, channel 2 output in bit 5
+const SPEAKER_CONTROL: Port<u8> = Port::new(0x61);
+const CH2_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CH2_OUT: u8 = 1 << 5;
+
 bitflags! {
     pub struct ControlWord: u8 {
         const CHANNEL0 = 0b00 << 6;
@@ -31,6 +38,11 @@ bitflags! {
     }
 }
 
+/// Divisor of the input clock closest to `frequency` from above
+pub const fn divisor(frequency: u32) -> u32 {
+    MAX_FREQ / frequency
+}
+
 pub struct Pit;
 
 impl Pit {
@@ -48,7 +60,28 @@ impl Pit {
             | ControlWord::BINARY;
         CONTROL.write(cw.bits());
 
-        self.set_divisor(MAX_FREQ / frequency);
+        self.set_divisor(divisor(frequency));
+    }
+
+    /// Busy waits for `count` periods of the input clock on channel 2, which
+    /// doesn't need interrupts
+    pub fn wait(&self, count: u16) {
+        // gate low while the count is written, with the speaker off
+        SPEAKER_CONTROL.update(|v| v & !(CH2_GATE | SPEAKER_ENABLE));
+
+        let cw = ControlWord::CHANNEL2
+            | ControlWord::LOW_BYTE
+            | ControlWord::HIGH_BYTE
+            | ControlWord::MODE_INT_TERMINAL_COUNT
+            | ControlWord::BINARY;
+        CONTROL.write(cw.bits());
+        CH2.write((count & 0xff) as _);
+        CH2.write((count >> 8 & 0xff) as _);
+
+        // raising the gate starts the count, the output goes high at zero
+        SPEAKER_CONTROL.update(|v| v | CH2_GATE);
+        while SPEAKER_CONTROL.read() & CH2_OUT == 0 {}
+        SPEAKER_CONTROL.update(|v| v & !CH2_GATE);
     }
 
     fn set_divisor(&self, divisor: u32) {
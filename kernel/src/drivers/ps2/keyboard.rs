@@ -1,4 +1,4 @@
-use crate::{drivers::ps2::KeyParser, interrupts::InterruptContext};
+use crate::{drivers::ps2::KeyParser, interrupts::InterruptContext, process::WaitQueue};
 use utils::key::{Key, KeyEvent};
 
 use super::super::port::Port;
@@ -31,6 +31,9 @@ pub static BUFFER: nullsync::RefCell<ringbuf::Ringbuf<Key, 1024>> =
 
 static PARSER: KeyParser = KeyParser::new();
 
+/// Processes waiting for a key press
+static READERS: WaitQueue = WaitQueue::new();
+
 impl PS2Keyboard {
     pub const fn new() -> Self {
         Self
@@ -56,7 +59,10 @@ impl PS2Keyboard {
 
     pub fn int_handler(ctx: &mut InterruptContext) {
         let code = DATA.read();
-        if let Ok(KeyEvent::Pressed(key)) = PARSER.parse(code) { BUFFER.borrow_mut().push(key) }
+        if let Ok(KeyEvent::Pressed(key)) = PARSER.parse(code) {
+            BUFFER.borrow_mut().push(key);
+            READERS.wake_all();
+        }
     }
 
     pub fn read(&self) -> u8 {
@@ -65,4 +71,14 @@ impl PS2Keyboard {
             None => 0,
         }
     }
+
+    /// Like `read`, but sleeps until a key is pressed
+    pub fn read_wait(&self) -> u8 {
+        loop {
+            match self.read() {
+                0 => READERS.sleep(),
+                key => return key,
+            }
+        }
+    }
 }
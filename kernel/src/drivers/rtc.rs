@@ -0,0 +1,214 @@
+use crate::{critical_section, interrupts::InterruptContext, time};
+
+use super::port::Port;
+use bitflags::bitflags;
+
+/// Register index in the low 7 bits, the top bit disables NMIs
+const INDEX: Port<u8> = Port::new(0x70);
+const DATA: Port<u8> = Port::new(0x71);
+const NMI_DISABLE: u8 = 0x80;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+
+/// Status A, the time registers are being updated
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Hours register in 12 hour mode
+const HOUR_PM: u8 = 0x80;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct StatusB: u8 {
+        const PERIODIC_INTERRUPT = 1 << 6;
+        const ALARM_INTERRUPT = 1 << 5;
+        const UPDATE_INTERRUPT = 1 << 4;
+        const BINARY = 1 << 2;
+        const HOUR_24 = 1 << 1;
+    }
+}
+
+bitflags! {
+    /// Interrupt sources, the same bits are set in status C when they fire
+    #[derive(Debug, Clone, Copy)]
+    pub struct RtcInterrupts: u8 {
+        /// 1024 Hz unless status A is changed
+        const PERIODIC = 1 << 6;
+        const ALARM = 1 << 5;
+        /// Once a second, right after the time registers changed
+        const UPDATE = 1 << 4;
+    }
+}
+
+/// Calendar time as the RTC keeps it, without a time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00
+    pub fn unix_timestamp(&self) -> u64 {
+        // days since the epoch of the proleptic gregorian calendar, with years
+        // starting in March so the leap day is the last one
+        let (year, month) = if self.month <= 2 {
+            (self.year as i64 - 1, self.month as i64 + 9)
+        } else {
+            (self.year as i64, self.month as i64 - 3)
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        let secs =
+            days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        secs.max(0) as u64
+    }
+
+    /// Inverse of `unix_timestamp`
+    pub fn from_unix_timestamp(secs: u64) -> Self {
+        let days = (secs / 86400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // months starting in March again
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let (year, month) = if month < 10 {
+            (year_of_era + era * 400, month + 3)
+        } else {
+            (year_of_era + era * 400 + 1, month - 9)
+        };
+
+        let secs_of_day = secs % 86400;
+        Self {
+            year: year as _,
+            month: month as _,
+            day: day as _,
+            hour: (secs_of_day / 3600) as _,
+            minute: (secs_of_day / 60 % 60) as _,
+            second: (secs_of_day % 60) as _,
+        }
+    }
+}
+
+/// Real time clock of the CMOS, it keeps the date while the machine is off
+pub struct Rtc;
+
+impl Rtc {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// Sets the wall clock from the RTC
+    pub fn init(&self) {
+        time::set_realtime(self.read().unix_timestamp());
+    }
+
+    /// Current date and time, always binary and 24 hour
+    pub fn read(&self) -> DateTime {
+        critical_section::wrap(|| {
+            // the registers may change between the reads, read until they
+            // are the same twice in a row
+            let mut time = read_raw();
+            loop {
+                let again = read_raw();
+                if again == time {
+                    break;
+                }
+                time = again;
+            }
+            decode(time, StatusB::from_bits_retain(read_register(STATUS_B)))
+        })
+    }
+
+    /// Raises IRQ 8 for `interrupts`, the others are turned off
+    pub fn enable_interrupts(&self, interrupts: RtcInterrupts) {
+        let mask =
+            StatusB::PERIODIC_INTERRUPT | StatusB::ALARM_INTERRUPT | StatusB::UPDATE_INTERRUPT;
+        critical_section::wrap(|| {
+            let status = StatusB::from_bits_retain(read_register(STATUS_B));
+            write_register(STATUS_B, ((status - mask).bits()) | interrupts.bits());
+            // a pending interrupt blocks new ones until status C is read
+            read_register(STATUS_C);
+        })
+    }
+
+    /// IRQ 8, the wall clock follows the RTC on every update
+    pub fn int_handler(_ctx: &mut InterruptContext) {
+        let fired = RtcInterrupts::from_bits_truncate(read_register(STATUS_C));
+        if fired.contains(RtcInterrupts::UPDATE) {
+            // the next update is a second away, the registers are stable
+            let status = StatusB::from_bits_retain(read_register(STATUS_B));
+            time::set_realtime(decode(read_raw(), status).unix_timestamp());
+        }
+    }
+}
+
+/// Time registers as they are stored, in BCD or binary
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct RawTime([u8; 6]);
+
+fn read_raw() -> RawTime {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+    RawTime([YEAR, MONTH, DAY, HOURS, MINUTES, SECONDS].map(read_register))
+}
+
+fn decode(RawTime(raw): RawTime, status: StatusB) -> DateTime {
+    let value = |raw: u8| {
+        if status.contains(StatusB::BINARY) {
+            raw
+        } else {
+            (raw >> 4) * 10 + (raw & 0x0f)
+        }
+    };
+    let [year, month, day, hours, minutes, seconds] = raw;
+
+    let mut hour = value(hours & !HOUR_PM);
+    if !status.contains(StatusB::HOUR_24) {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if hours & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    DateTime {
+        // the century register is not at a standard index, assume this one
+        year: 2000 + value(year) as u16,
+        month: value(month),
+        day: value(day),
+        hour,
+        minute: value(minutes),
+        second: value(seconds),
+    }
+}
+
+/// NMIs are masked only while the register is accessed
+fn read_register(index: u8) -> u8 {
+    INDEX.write(NMI_DISABLE | index);
+    let value = DATA.read();
+    INDEX.write(index);
+    value
+}
+
+fn write_register(index: u8, value: u8) {
+    INDEX.write(NMI_DISABLE | index);
+    DATA.write(value);
+    INDEX.write(index);
+}
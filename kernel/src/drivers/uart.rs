@@ -0,0 +1,244 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{critical_section, process::WaitQueue};
+
+use super::port::Port;
+use bitflags::bitflags;
+use utils::{io, nullsync, ringbuf::Ringbuf};
+
+pub const COM1: u16 = 0x3f8;
+pub const COM2: u16 = 0x2f8;
+
+/// Frequency the divisor divides
+const BASE_BAUD: u32 = 115_200;
+const FIFO_SIZE: usize = 16;
+
+// register offsets, DATA and INTERRUPT_ENABLE are the divisor while LCR.DLAB is set
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct InterruptEnable: u8 {
+        const RECEIVED = 1 << 0;
+        const TRANSMIT_EMPTY = 1 << 1;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct LineControl: u8 {
+        const EIGHT_BITS = 0b11;
+        const DLAB = 1 << 7;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct ModemControl: u8 {
+        const DTR = 1 << 0;
+        const RTS = 1 << 1;
+        /// Connects the interrupt line to the PIC
+        const OUT2 = 1 << 3;
+        const LOOPBACK = 1 << 4;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    struct LineStatus: u8 {
+        const DATA_READY = 1 << 0;
+        const TRANSMIT_EMPTY = 1 << 5;
+    }
+}
+
+/// FIFOs on, both cleared, receive interrupt at 14 bytes
+const FIFO_ENABLE: u8 = 0b1100_0111;
+/// Interrupt id register, no interrupt is pending
+const NO_INTERRUPT: u8 = 1;
+
+/// 16550 compatible serial port. Received bytes are buffered by the interrupt
+/// handler, written ones are sent from a buffer whenever the transmitter is
+/// empty. Without interrupts the port is polled.
+pub struct Uart {
+    base: u16,
+    present: AtomicBool,
+    interrupts: AtomicBool,
+    rx: nullsync::RefCell<Ringbuf<u8, 1024>>,
+    tx: nullsync::RefCell<Ringbuf<u8, 4096>>,
+    /// Processes waiting for a byte
+    readers: WaitQueue,
+}
+
+impl Uart {
+    pub const fn new(base: u16) -> Self {
+        Self {
+            base,
+            present: AtomicBool::new(false),
+            interrupts: AtomicBool::new(false),
+            rx: nullsync::RefCell::new(Ringbuf::new()),
+            tx: nullsync::RefCell::new(Ringbuf::new()),
+            readers: WaitQueue::new(),
+        }
+    }
+
+    /// Sets the port up for 8N1 at `baud`, returns false if there is no port
+    pub fn init(&self, baud: u32) -> bool {
+        debug_assert!(BASE_BAUD.is_multiple_of(baud));
+        let divisor = (BASE_BAUD / baud) as u16;
+
+        self.port(INTERRUPT_ENABLE).write(0);
+        self.port(LINE_CONTROL).write(LineControl::DLAB.bits());
+        self.port(DATA).write(divisor as u8);
+        self.port(INTERRUPT_ENABLE).write((divisor >> 8) as u8);
+        self.port(LINE_CONTROL)
+            .write(LineControl::EIGHT_BITS.bits());
+        self.port(FIFO_CONTROL).write(FIFO_ENABLE);
+
+        // a byte sent in loopback mode must come back
+        let modem = ModemControl::DTR | ModemControl::RTS | ModemControl::OUT2;
+        self.port(MODEM_CONTROL)
+            .write((modem | ModemControl::LOOPBACK).bits());
+        self.port(DATA).write(0xae);
+        let present = self.port(DATA).read() == 0xae;
+        self.port(MODEM_CONTROL).write(modem.bits());
+
+        self.present.store(present, Ordering::Relaxed);
+        present
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// Switches from polling to interrupts, the IRQ handler must be registered
+    pub fn enable_interrupts(&self) {
+        if !self.is_present() {
+            return;
+        }
+        self.interrupts.store(true, Ordering::Relaxed);
+        self.port(INTERRUPT_ENABLE)
+            .write(InterruptEnable::RECEIVED.bits());
+        critical_section::wrap(|| self.transmit());
+    }
+
+    /// Queues `buf` for sending. Waits for the port only while the buffer is full.
+    pub fn write(&self, buf: &[u8]) {
+        if !self.is_present() {
+            return;
+        }
+
+        critical_section::wrap(|| {
+            for &byte in buf {
+                if self.tx.borrow_mut().is_full() {
+                    self.flush();
+                }
+                self.tx.borrow_mut().push(byte);
+            }
+
+            if self.interrupts.load(Ordering::Relaxed) {
+                self.transmit();
+            } else {
+                self.flush();
+            }
+        })
+    }
+
+    /// Sends everything queued, polling the port
+    pub fn flush(&self) {
+        if !self.is_present() {
+            return;
+        }
+
+        while let Some(byte) = self.tx.borrow_mut().pop() {
+            while !self.line_status().contains(LineStatus::TRANSMIT_EMPTY) {}
+            self.port(DATA).write(byte);
+        }
+    }
+
+    /// Next received byte
+    pub fn read(&self) -> Option<u8> {
+        if !self.interrupts.load(Ordering::Relaxed) {
+            let ready = self.is_present() && self.line_status().contains(LineStatus::DATA_READY);
+            return ready.then(|| self.port(DATA).read());
+        }
+        critical_section::wrap(|| self.rx.borrow_mut().pop())
+    }
+
+    /// Like `read`, but sleeps until a byte arrives. Interrupts must be enabled.
+    pub fn read_wait(&self) -> u8 {
+        debug_assert!(self.interrupts.load(Ordering::Relaxed));
+        loop {
+            match self.read() {
+                Some(byte) => return byte,
+                None => self.readers.sleep(),
+            }
+        }
+    }
+
+    /// Handles every pending interrupt of the port
+    pub fn handle_interrupt(&self) {
+        let mut received = false;
+        while self.port(INTERRUPT_ID).read() & NO_INTERRUPT == 0 {
+            while self.line_status().contains(LineStatus::DATA_READY) {
+                let byte = self.port(DATA).read();
+                let mut rx = self.rx.borrow_mut();
+                // the oldest bytes are kept if nobody reads
+                if !rx.is_full() {
+                    rx.push(byte);
+                }
+                received = true;
+            }
+            self.transmit();
+        }
+
+        if received {
+            self.readers.wake_all();
+        }
+    }
+
+    /// Fills the transmitter FIFO if it is empty. The transmit interrupt stays
+    /// on while bytes are queued.
+    fn transmit(&self) {
+        let mut tx = self.tx.borrow_mut();
+        if self.line_status().contains(LineStatus::TRANSMIT_EMPTY) {
+            for _ in 0..FIFO_SIZE {
+                let Some(byte) = tx.pop() else {
+                    break;
+                };
+                self.port(DATA).write(byte);
+            }
+        }
+
+        let mut enable = InterruptEnable::RECEIVED;
+        if !tx.is_empty() {
+            enable |= InterruptEnable::TRANSMIT_EMPTY;
+        }
+        self.port(INTERRUPT_ENABLE).write(enable.bits());
+    }
+
+    fn line_status(&self) -> LineStatus {
+        LineStatus::from_bits_retain(self.port(LINE_STATUS).read())
+    }
+
+    const fn port(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+}
+
+impl io::Write for &Uart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Uart::write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Uart::flush(self);
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use core::{mem, ptr};
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const CLASS_32: u8 = 1;
+const DATA_LSB: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    Unsupported,
+    BadSegment,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Header {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u32,
+    phoff: u32,
+    shoff: u32,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgramHeader {
+    kind: u32,
+    offset: u32,
+    vaddr: u32,
+    paddr: u32,
+    filesz: u32,
+    memsz: u32,
+    flags: u32,
+    align: u32,
+}
+
+/// Executable for i386, validated so that every segment lies inside the image
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+pub struct Segment<'a> {
+    pub vaddr: usize,
+    pub memsz: usize,
+    /// File part of the segment, the rest up to `memsz` is zero
+    pub data: &'a [u8],
+    pub flags: u32,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header: Header = read(data, 0).ok_or(ElfError::TooShort)?;
+
+        if header.ident[..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != CLASS_32
+            || header.ident[5] != DATA_LSB
+            || header.kind != TYPE_EXEC
+            || header.machine != MACHINE_386
+            || (header.phentsize as usize) < mem::size_of::<ProgramHeader>()
+        {
+            return Err(ElfError::Unsupported);
+        }
+
+        let elf = Self { data, header };
+        for i in 0..header.phnum as usize {
+            let ph = elf.program_header(i).ok_or(ElfError::TooShort)?;
+            let file_end = ph.offset.checked_add(ph.filesz);
+            let mem_end = ph.vaddr.checked_add(ph.memsz);
+
+            if ph.kind == PT_LOAD
+                && (ph.filesz > ph.memsz
+                    || file_end.is_none_or(|end| end as usize > data.len())
+                    || mem_end.is_none())
+            {
+                return Err(ElfError::BadSegment);
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn entry(&self) -> usize {
+        self.header.entry as _
+    }
+
+    /// PT_LOAD segments
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + '_ {
+        (0..self.header.phnum as usize)
+            .filter_map(|i| self.program_header(i))
+            .filter(|ph| ph.kind == PT_LOAD)
+            .map(|ph| Segment {
+                vaddr: ph.vaddr as _,
+                memsz: ph.memsz as _,
+                data: &self.data[ph.offset as usize..(ph.offset + ph.filesz) as usize],
+                flags: ph.flags,
+            })
+    }
+
+    fn program_header(&self, index: usize) -> Option<ProgramHeader> {
+        let offset = self.header.phoff as usize + index * self.header.phentsize as usize;
+        read(self.data, offset)
+    }
+}
+
+fn read<T: Copy>(data: &[u8], offset: usize) -> Option<T> {
+    let bytes = data.get(offset..offset.checked_add(mem::size_of::<T>())?)?;
+    Some(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
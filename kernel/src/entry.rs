@@ -1,5 +1,103 @@
+use crate::{
+    boot_info::{self, FramebufferInfo, MemoryKind, MemoryRegion, Module},
+    drivers::bga::Bga,
+    global_alloc, multiboot, paging,
+    x86_utils::hlt,
+};
+
+const E820_MAX_ENTRIES: usize = 32;
+
+#[repr(C, packed)]
+struct E820Entry {
+    base: u64,
+    len: u64,
+    kind: u32,
+    attributes: u32,
+}
+
+// Filled by the boot sector, read through the identity mapping that lives until kmain
+unsafe extern "C" {
+    static framebuffer_addr: *mut u32;
+    static framebuffer_width: u16;
+    static framebuffer_height: u16;
+    static memory_map_count: u16;
+    static memory_map_entries: [E820Entry; E820_MAX_ENTRIES];
+}
+
+/// Raw user programs that the boot sector reads along with the kernel
+const LEGACY_MODULES: [(usize, &[u8]); 4] = [
+    (0x60000, b"userspace1"),
+    (0x68000, b"userspace2"),
+    (0x70000, b"userspace3"),
+    (0x78000, b"userspace4"),
+];
+const LEGACY_MODULE_SIZE: usize = 0x8000;
+/// Archive the boot sector reads after the programs, os.img has zeroes there
+/// if none was appended. It has to end below the EBDA.
+const LEGACY_INITRD: usize = 0x80000;
+const LEGACY_INITRD_SIZE: usize = 0x18000;
+
 #[unsafe(no_mangle)]
 pub extern "C" fn kentry() -> ! {
+    let info = unsafe { boot_info::boot_info_mut() };
+
+    info.framebuffer = unsafe {
+        FramebufferInfo {
+            addr: framebuffer_addr as _,
+            pitch: framebuffer_width as usize * 4,
+            width: framebuffer_width as _,
+            height: framebuffer_height as _,
+            bpp: 32,
+        }
+    };
+
+    let entries = unsafe { &memory_map_entries[..memory_map_count as usize] };
+    // ACPI 3.0: entries with cleared bit 0 of attributes must be ignored
+    for entry in entries.iter().filter(|entry| entry.attributes & 1 != 0) {
+        info.push_memory_region(MemoryRegion {
+            start: entry.base,
+            len: entry.len,
+            kind: MemoryKind::from_e820(entry.kind),
+        });
+    }
+
+    for (addr, name) in LEGACY_MODULES {
+        info.push_module(Module::new(addr, addr + LEGACY_MODULE_SIZE, name));
+    }
+    let initrd = unsafe { &*paging::phys_to_virt::<[u8; 512]>(LEGACY_INITRD) };
+    if initrd.iter().any(|&byte| byte != 0) {
+        info.push_module(Module::new(
+            LEGACY_INITRD,
+            LEGACY_INITRD + LEGACY_INITRD_SIZE,
+            b"initrd",
+        ));
+    }
+
+    crate::kmain();
+    loop {
+        hlt();
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn kentry_multiboot(magic: u32, mbi: u32) -> ! {
+    let info = unsafe { boot_info::boot_info_mut() };
+    multiboot::parse(magic, paging::phys_to_virt(mbi as usize), info);
+
+    // the text buffer draws 32-bit pixels only
+    if info.framebuffer.is_empty() || info.framebuffer.bpp != 32 {
+        info.framebuffer = Bga::new()
+            .set_mode(640, 400, 32)
+            .expect("No framebuffer from the bootloader and no Bochs display adapter");
+    }
+
+    let heap = global_alloc::arena();
+    info.evacuate_modules(
+        paging::virt_to_phys(heap.start as *const u8)..paging::virt_to_phys(heap.end as *const u8),
+    );
+
     crate::kmain();
-    loop {}
+    loop {
+        hlt();
+    }
 }
@@ -0,0 +1,170 @@
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use utils::io::Write;
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+use crate::{device_manager::DEVICES, drivers::uart::Uart, process::get_cur_process};
+
+/// Device files: `console` is the terminal of the process using it,
+/// `ttyS0` and `ttyS1` are the serial ports
+pub struct DevFs {
+    root: Arc<DevDir>,
+}
+
+struct DevDir {
+    entries: Vec<(&'static str, Arc<dyn Inode>)>,
+}
+
+struct Console;
+
+struct Serial {
+    uart: &'static Uart,
+    ino: u64,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        let mut entries: Vec<(&'static str, Arc<dyn Inode>)> = alloc::vec![("console", console())];
+        for (name, uart, ino) in [("ttyS0", &DEVICES.com1, 3), ("ttyS1", &DEVICES.com2, 4)] {
+            if uart.is_present() {
+                entries.push((name, Arc::new(Serial { uart, ino })));
+            }
+        }
+
+        Arc::new(Self {
+            root: Arc::new(DevDir { entries }),
+        })
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// Terminal of the current process
+pub fn console() -> Arc<dyn Inode> {
+    Arc::new(Console)
+}
+
+impl Inode for DevDir {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: 1,
+            kind: FileType::Directory,
+            mode: 0o755,
+            nlink: 2,
+            size: 0,
+            mtime: 0,
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|(name, inode)| {
+                let stat = inode.stat();
+                DirEntry {
+                    name: String::from(*name),
+                    ino: stat.ino,
+                    kind: stat.kind,
+                }
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| *entry == name)
+            .map(|(_, inode)| inode.clone())
+            .ok_or(FsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+/// Device file number `ino`
+fn device_stat(ino: u64) -> Stat {
+    Stat {
+        ino,
+        kind: FileType::CharDevice,
+        mode: 0o620,
+        nlink: 1,
+        size: 0,
+        mtime: 0,
+    }
+}
+
+impl Console {
+    /// Input of the terminal: key codes on the screen, bytes on a serial
+    /// port. Everything that arrived is returned, waiting only for the first.
+    fn read(&self, buf: &mut [u8], wait: bool) -> usize {
+        let tty = &get_cur_process().tty;
+        let mut count = 0;
+        while count < buf.len() {
+            match tty.read(wait && count == 0) {
+                0 => break,
+                byte => buf[count] = byte,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl Inode for Console {
+    fn stat(&self) -> Stat {
+        device_stat(2)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(self.read(buf, true))
+    }
+
+    fn read_nonblock(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(self.read(buf, false))
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        get_cur_process().tty.write(buf).map_err(|_| FsError::Io)
+    }
+}
+
+impl Inode for Serial {
+    fn stat(&self) -> Stat {
+        device_stat(self.ino)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let Some(first) = buf.first_mut() else {
+            return Ok(0);
+        };
+        *first = self.uart.read_wait();
+        Ok(1 + self.read_nonblock(0, &mut buf[1..])?)
+    }
+
+    fn read_nonblock(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut count = 0;
+        while let Some(slot) = buf.get_mut(count)
+            && let Some(byte) = self.uart.read()
+        {
+            *slot = byte;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.uart.write(buf);
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,451 @@
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+use crate::drivers::block::BlockDevice;
+
+/// Byte offset and size of the superblock, whatever the block size
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INO: u32 = 2;
+/// Inode size of revision 0 file systems, later ones store it
+const OLD_INODE_SIZE: u64 = 128;
+const GROUP_DESC_SIZE: usize = 32;
+
+/// The only incompatible feature understood: directory entries with the file type
+const INCOMPAT_FILETYPE: u32 = 0x2;
+
+/// Block pointers in an inode: direct ones, then the single, doubly and
+/// triply indirect block
+const DIRECT_BLOCKS: usize = 12;
+const BLOCK_POINTERS: usize = 15;
+/// Symbolic links shorter than this keep the target in the block pointers
+const FAST_SYMLINK_MAX: u64 = 60;
+
+/// File types of `i_mode`
+const S_IFMT: u16 = 0xf000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+const S_IFLNK: u16 = 0xa000;
+
+/// File types of directory entries
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_SYMLINK: u8 = 7;
+
+/// Read only ext2 volume. Files that are no regular files, directories,
+/// symbolic links or character devices are left out.
+pub struct Ext2Fs {
+    disk: &'static dyn BlockDevice,
+    block_size: u64,
+    inodes: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    /// Directory entries store the file type
+    filetype: bool,
+    /// First block of the inode table of each block group
+    inode_tables: Vec<u32>,
+    root: RawInode,
+    this: Weak<Ext2Fs>,
+}
+
+/// Fields of an inode on the disk
+#[derive(Clone, Copy, Default)]
+struct RawInode {
+    mode: u16,
+    size: u64,
+    mtime: u32,
+    nlink: u16,
+    /// 512 byte sectors in use, the extended attribute block included
+    sectors: u32,
+    /// Extended attribute block, 0 if there is none
+    file_acl: u32,
+    block: [u32; BLOCK_POINTERS],
+}
+
+pub struct Ext2Node {
+    fs: Arc<Ext2Fs>,
+    ino: u32,
+    kind: FileType,
+    raw: RawInode,
+}
+
+impl Ext2Fs {
+    /// Mounts the volume on `disk`, None if it does not hold one or uses
+    /// features this driver does not know
+    pub fn mount(disk: &'static dyn BlockDevice) -> Option<Arc<Self>> {
+        let disk_block = disk.block_size() as u64;
+        if disk_block == 0 || !SUPERBLOCK_OFFSET.is_multiple_of(disk_block) {
+            return None;
+        }
+        let mut sb = vec![0; SUPERBLOCK_SIZE];
+        disk.read_blocks(SUPERBLOCK_OFFSET / disk_block, &mut sb)
+            .ok()?;
+        let u16_at = |offset: usize| u16::from_le_bytes([sb[offset], sb[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes(sb[offset..offset + 4].try_into().unwrap());
+
+        let inodes = u32_at(0);
+        let blocks = u32_at(4) as u64;
+        let first_data_block = u32_at(20) as u64;
+        let log_block_size = u32_at(24);
+        let blocks_per_group = u32_at(32) as u64;
+        let inodes_per_group = u32_at(40);
+        let revision = u32_at(76);
+        let (inode_size, incompat) = match revision {
+            0 => (OLD_INODE_SIZE, 0),
+            _ => (u16_at(88) as u64, u32_at(96)),
+        };
+
+        if u16_at(56) != MAGIC || log_block_size > 6 {
+            return None;
+        }
+        let block_size = 1024 << log_block_size;
+        let valid = block_size % disk_block == 0
+            && blocks_per_group > 0
+            && inodes_per_group > 0
+            && inode_size.is_power_of_two()
+            && (OLD_INODE_SIZE..=block_size).contains(&inode_size)
+            && blocks > first_data_block
+            && blocks * block_size <= disk.block_count() * disk_block;
+        if !valid || incompat & !INCOMPAT_FILETYPE != 0 {
+            return None;
+        }
+
+        // the group descriptors follow the block of the superblock
+        let groups = (blocks - first_data_block).div_ceil(blocks_per_group) as usize;
+        let mut descs = vec![0; (groups * GROUP_DESC_SIZE).next_multiple_of(block_size as usize)];
+        let descs_start = (first_data_block + 1) * block_size / disk_block;
+        disk.read_blocks(descs_start, &mut descs).ok()?;
+        let inode_tables = descs
+            .chunks_exact(GROUP_DESC_SIZE)
+            .take(groups)
+            .map(|desc| u32::from_le_bytes(desc[8..12].try_into().unwrap()))
+            .collect();
+
+        let mut fs = Self {
+            disk,
+            block_size,
+            inodes,
+            inodes_per_group,
+            inode_size,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            inode_tables,
+            root: RawInode::default(),
+            this: Weak::new(),
+        };
+        fs.root = fs.read_inode(ROOT_INO).ok()?;
+        if fs.root.kind() != Some(FileType::Directory) {
+            return None;
+        }
+        Some(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            ..fs
+        }))
+    }
+
+    /// Reads the block `block` into `buf`, which is as large as a block
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        let disk_block = self.disk.block_size() as u64;
+        self.disk
+            .read_blocks(block as u64 * self.block_size / disk_block, buf)?;
+        Ok(())
+    }
+
+    /// Entry `index` of the block `block` of block numbers
+    fn read_pointer(&self, block: u32, index: u64) -> Result<u32, FsError> {
+        // only the disk block with the entry is read
+        let disk_block = self.disk.block_size() as u64;
+        let offset = block as u64 * self.block_size + index * 4;
+        let mut buf = vec![0; disk_block as usize];
+        self.disk.read_blocks(offset / disk_block, &mut buf)?;
+        let within = (offset % disk_block) as usize;
+        Ok(u32::from_le_bytes(
+            buf[within..within + 4].try_into().unwrap(),
+        ))
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<RawInode, FsError> {
+        if ino == 0 || ino > self.inodes {
+            return Err(FsError::Io);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as u64;
+        let table = *self.inode_tables.get(group).ok_or(FsError::Io)?;
+
+        let offset = table as u64 * self.block_size + index * self.inode_size;
+        let mut block = vec![0; self.block_size as usize];
+        self.read_block((offset / self.block_size) as u32, &mut block)?;
+        let raw = &block[(offset % self.block_size) as usize..];
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        // regular files keep the upper half of the size where directories
+        // have i_dir_acl
+        let mode = u16_at(0);
+        let size_high = if mode & S_IFMT == S_IFREG {
+            u32_at(108) as u64
+        } else {
+            0
+        };
+        Ok(RawInode {
+            mode,
+            size: size_high << 32 | u32_at(4) as u64,
+            mtime: u32_at(16),
+            nlink: u16_at(26),
+            sectors: u32_at(28),
+            file_acl: u32_at(104),
+            block: core::array::from_fn(|i| u32_at(40 + i * 4)),
+        })
+    }
+
+    /// Node of the inode `ino`
+    fn node(&self, ino: u32, raw: RawInode) -> Result<Arc<Ext2Node>, FsError> {
+        Ok(Arc::new(Ext2Node {
+            fs: self.this.upgrade().unwrap(),
+            ino,
+            kind: raw.kind().ok_or(FsError::Unsupported)?,
+            raw,
+        }))
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.node(ROOT_INO, self.root).unwrap()
+    }
+}
+
+impl RawInode {
+    fn kind(&self) -> Option<FileType> {
+        match self.mode & S_IFMT {
+            S_IFREG => Some(FileType::Regular),
+            S_IFDIR => Some(FileType::Directory),
+            S_IFLNK => Some(FileType::Symlink),
+            S_IFCHR => Some(FileType::CharDevice),
+            _ => None,
+        }
+    }
+}
+
+impl Ext2Node {
+    /// Disk block of block `index` of the file, 0 for a hole
+    fn map_block(&self, index: u64) -> Result<u32, FsError> {
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok(self.raw.block[index as usize]);
+        }
+
+        // the single, doubly and triply indirect blocks each cover the
+        // number of pointers in a block times as many blocks as the one before
+        let pointers = self.fs.block_size / 4;
+        let mut index = index - DIRECT_BLOCKS as u64;
+        let mut covered = pointers;
+        for depth in 1..=3 {
+            if index < covered {
+                let mut block = self.raw.block[DIRECT_BLOCKS + depth - 1];
+                for level in (0..depth as u32).rev() {
+                    if block == 0 {
+                        break;
+                    }
+                    block = self
+                        .fs
+                        .read_pointer(block, index / pointers.pow(level) % pointers)?;
+                }
+                return Ok(block);
+            }
+            index -= covered;
+            covered *= pointers;
+        }
+        Err(FsError::Io)
+    }
+
+    /// Reads the content of the node, which must be in the file
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let block_size = self.fs.block_size;
+        let mut block = vec![0; block_size as usize];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % block_size) as usize;
+            let len = (buf.len() - done).min(block.len() - within);
+            match self.map_block(pos / block_size)? {
+                0 => block.fill(0),
+                disk_block => self.fs.read_block(disk_block, &mut block)?,
+            }
+            buf[done..done + len].copy_from_slice(&block[within..within + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Entries of the directory: inode number, name and the file type byte,
+    /// which is 0 if the file system does not store it
+    fn entries(&self) -> Result<Vec<(u32, Vec<u8>, u8)>, FsError> {
+        let mut data = vec![0; self.raw.size as usize];
+        self.read_data(0, &mut data)?;
+
+        let mut entries = Vec::new();
+        for block in data.chunks(self.fs.block_size as usize) {
+            let mut offset = 0;
+            // entries never cross blocks, the last one fills its block
+            while offset + 8 <= block.len() {
+                let entry = &block[offset..];
+                let ino = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let rec_len = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+                let (name_len, file_type) = if self.fs.filetype {
+                    (entry[6] as usize, entry[7])
+                } else {
+                    (u16::from_le_bytes([entry[6], entry[7]]) as usize, 0)
+                };
+                if rec_len < 8 || rec_len > entry.len() || 8 + name_len > rec_len {
+                    return Err(FsError::Io);
+                }
+
+                // inode 0 marks unused space
+                if ino != 0 {
+                    entries.push((ino, entry[8..8 + name_len].to_vec(), file_type));
+                }
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn check_dir(&self) -> Result<(), FsError> {
+        match self.kind {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+impl Inode for Ext2Node {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: self.ino as _,
+            kind: self.kind,
+            mode: self.raw.mode & 0o7777,
+            nlink: self.raw.nlink as _,
+            size: self.raw.size,
+            mtime: self.raw.mtime as _,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind != FileType::Regular {
+            return Err(self.unsupported());
+        }
+        if offset >= self.raw.size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((self.raw.size - offset) as usize);
+        self.read_data(offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        match self.kind {
+            FileType::Regular => Err(FsError::ReadOnly),
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        self.write_at(0, &[]).map(|_| ())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_dir()?;
+
+        let mut entries = Vec::new();
+        for (ino, name, file_type) in self.entries()? {
+            let kind = match file_type {
+                FT_REG_FILE => Some(FileType::Regular),
+                FT_DIR => Some(FileType::Directory),
+                FT_CHRDEV => Some(FileType::CharDevice),
+                FT_SYMLINK => Some(FileType::Symlink),
+                _ if self.fs.filetype => None,
+                _ => self.fs.read_inode(ino)?.kind(),
+            };
+            if let Some(kind) = kind {
+                entries.push(DirEntry {
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    ino: ino as _,
+                    kind,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        if self.kind != FileType::Symlink {
+            return Err(FsError::InvalidArgs);
+        }
+
+        // short targets are stored in place of the block pointers, unless
+        // the link has blocks besides the extended attribute one
+        let acl_sectors = match self.raw.file_acl {
+            0 => 0,
+            _ => (self.fs.block_size / 512) as u32,
+        };
+        let mut target = vec![0; self.raw.size as usize];
+        if self.raw.size < FAST_SYMLINK_MAX && self.raw.sectors == acl_sectors {
+            let bytes = self.raw.block.iter().flat_map(|block| block.to_le_bytes());
+            for (byte, stored) in target.iter_mut().zip(bytes) {
+                *byte = stored;
+            }
+        } else {
+            self.read_data(0, &mut target)?;
+        }
+        String::from_utf8(target).map_err(|_| FsError::Io)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        let (ino, _, _) = self
+            .entries()?
+            .into_iter()
+            .find(|(_, entry, _)| entry == name.as_bytes())
+            .ok_or(FsError::NotFound)?;
+        Ok(self.fs.node(ino, self.fs.read_inode(ino)?)?)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&self, _name: &str, _dir: &Arc<dyn Inode>, _new_name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+}
@@ -0,0 +1,314 @@
+use alloc::{string::String, vec::Vec};
+
+use crate::drivers::rtc::DateTime;
+
+pub const ENTRY_SIZE: usize = 32;
+/// Characters of a long name in one entry
+const LFN_CHARS: usize = 13;
+pub const MAX_NAME: usize = 255;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Read only, hidden, system and volume id together mark a long name entry
+const ATTR_LFN: u8 = 0x0f;
+
+/// First name byte of free entries, END also means all entries after are free
+pub const DELETED: u8 = 0xe5;
+const END: u8 = 0x00;
+/// Stored instead of a leading 0xe5, which means deleted
+const KANJI_E5: u8 = 0x05;
+/// Sequence number flag of the long name entry stored first
+const LAST_LFN: u8 = 0x40;
+
+/// Case flags of a short name, set by Windows and mtools instead of a long name
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXT: u8 = 0x10;
+
+/// Short name characters besides letters and digits
+const SHORT_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// 32 byte directory entry
+#[derive(Clone, Copy)]
+pub struct RawEntry(pub [u8; ENTRY_SIZE]);
+
+/// Short entry with the long name in front of it
+pub struct DirEntry {
+    pub name: String,
+    pub short: [u8; 11],
+    pub raw: RawEntry,
+    /// Offset of the short entry in the directory
+    pub offset: u64,
+    /// Offset of the first long name entry, or of the short one without a long name
+    pub first: u64,
+}
+
+impl RawEntry {
+    pub fn new(short: [u8; 11], attr: u8, first_cluster: u32, mtime: u64) -> Self {
+        let mut raw = Self([0; ENTRY_SIZE]);
+        raw.0[..11].copy_from_slice(&short);
+        raw.0[11] = attr;
+        raw.set_first_cluster(first_cluster);
+        raw.set_mtime(mtime);
+        raw
+    }
+
+    pub fn attr(&self) -> u8 {
+        self.0[11]
+    }
+
+    pub fn first_cluster(&self) -> u32 {
+        (self.u16_at(20) as u32) << 16 | self.u16_at(26) as u32
+    }
+
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.0[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        self.0[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+
+    pub fn size(&self) -> u32 {
+        u32::from_le_bytes(self.0[28..32].try_into().unwrap())
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.0[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Last modification, seconds since the unix epoch. FAT stores local
+    /// time, it is taken as UTC.
+    pub fn mtime(&self) -> u64 {
+        let (time, date) = (self.u16_at(22), self.u16_at(24));
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: (date >> 5 & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: (time >> 5 & 0x3f) as u8,
+            second: (time & 0x1f) as u8 * 2,
+        }
+        .unix_timestamp()
+    }
+
+    pub fn set_mtime(&mut self, secs: u64) {
+        let date = DateTime::from_unix_timestamp(secs);
+        // dates before 1980 can not be stored
+        let year = date.year.saturating_sub(1980).min(127);
+        let time =
+            ((date.hour as u16) << 11) | ((date.minute as u16) << 5) | (date.second as u16 / 2);
+        let date = (year << 9) | ((date.month as u16) << 5) | date.day as u16;
+        self.0[22..24].copy_from_slice(&time.to_le_bytes());
+        self.0[24..26].copy_from_slice(&date.to_le_bytes());
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+}
+
+/// Entries of the directory content `data`, without the volume label
+pub fn parse(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    // long name parts in order, the offset of the first entry and the checksum
+    let mut lfn: Option<(Vec<u16>, u64, u8)> = None;
+
+    for (i, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        let offset = (i * ENTRY_SIZE) as u64;
+        let raw = RawEntry(raw.try_into().unwrap());
+        match raw.0[0] {
+            END => break,
+            DELETED => {
+                lfn = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        if raw.attr() == ATTR_LFN {
+            let seq = (raw.0[0] & 0x1f) as usize;
+            if raw.0[0] & LAST_LFN != 0 {
+                lfn = Some((alloc::vec![0xffff; seq * LFN_CHARS], offset, raw.0[13]));
+            }
+            match &mut lfn {
+                Some((chars, _, checksum)) if seq >= 1 && seq * LFN_CHARS <= chars.len() => {
+                    if *checksum != raw.0[13] {
+                        lfn = None;
+                        continue;
+                    }
+                    let part = &mut chars[(seq - 1) * LFN_CHARS..seq * LFN_CHARS];
+                    for (char, pos) in part.iter_mut().zip(lfn_positions()) {
+                        *char = raw.u16_at(pos);
+                    }
+                }
+                _ => lfn = None,
+            }
+            continue;
+        }
+
+        let lfn = lfn.take();
+        if raw.attr() & ATTR_VOLUME_ID != 0 {
+            continue;
+        }
+        let mut short: [u8; 11] = raw.0[..11].try_into().unwrap();
+        if short[0] == KANJI_E5 {
+            short[0] = DELETED;
+        }
+        let (name, first) = match lfn {
+            Some((chars, first, sum)) if sum == checksum(&raw.0[..11]) => {
+                let len = chars.iter().position(|&c| c == 0 || c == 0xffff);
+                let name = char::decode_utf16(chars[..len.unwrap_or(chars.len())].iter().copied())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                (name, first)
+            }
+            _ => (short_to_string(&short, raw.0[12]), offset),
+        };
+
+        entries.push(DirEntry {
+            name,
+            short,
+            raw,
+            offset,
+            first,
+        });
+    }
+    entries
+}
+
+/// Entries storing `name`, the long name ones first and the short one last
+pub fn build(name: &str, short: [u8; 11], raw: RawEntry) -> Vec<RawEntry> {
+    let mut raw = raw;
+    raw.0[..11].copy_from_slice(&short);
+    if raw.0[0] == DELETED {
+        raw.0[0] = KANJI_E5;
+    }
+
+    let mut entries = Vec::new();
+    if name != short_to_string(&short, 0) {
+        let chars = name.encode_utf16().collect::<Vec<_>>();
+        let count = chars.len().div_ceil(LFN_CHARS);
+        let sum = checksum(&raw.0[..11]);
+
+        for seq in (1..=count).rev() {
+            let mut lfn = RawEntry([0; ENTRY_SIZE]);
+            lfn.0[0] = seq as u8 | if seq == count { LAST_LFN } else { 0 };
+            lfn.0[11] = ATTR_LFN;
+            lfn.0[13] = sum;
+            for (i, pos) in lfn_positions().enumerate() {
+                // NUL terminated unless it fills the entry, then padded with 0xffff
+                let char = match ((seq - 1) * LFN_CHARS + i).cmp(&chars.len()) {
+                    core::cmp::Ordering::Less => chars[(seq - 1) * LFN_CHARS + i],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                lfn.0[pos..pos + 2].copy_from_slice(&char.to_le_bytes());
+            }
+            entries.push(lfn);
+        }
+    }
+
+    entries.push(raw);
+    entries
+}
+
+/// Checks that `name` can be a file name, long names allow most characters
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME
+        && !name.ends_with(['.', ' '])
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+/// Short name for `name` that is not in `taken`. Names that already are
+/// upper case 8.3 names are kept, others get a numeric tail like `LONGNA~1`.
+pub fn short_name(name: &str, taken: &[[u8; 11]]) -> Option<[u8; 11]> {
+    if let Some(short) = exact_short_name(name)
+        && !taken.contains(&short)
+    {
+        return Some(short);
+    }
+
+    let (base, ext) = match name.trim_start_matches('.').rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (base, ext),
+        _ => (name.trim_start_matches('.'), ""),
+    };
+    let convert = |part: &str| {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.to_ascii_uppercase() {
+                c if c.is_ascii_alphanumeric() || SHORT_SPECIAL.contains(&(c as u8)) => c as u8,
+                _ => b'_',
+            })
+            .collect::<Vec<_>>()
+    };
+    let (base, ext) = (convert(base), convert(ext));
+
+    let mut short = [b' '; 11];
+    for (slot, &c) in short[8..].iter_mut().zip(&ext) {
+        *slot = c;
+    }
+    (1..1_000_000).find_map(|n| {
+        let tail = alloc::format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        short[..8].fill(b' ');
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        (!taken.contains(&short)).then_some(short)
+    })
+}
+
+/// `name` as a short name if it is a valid upper case 8.3 name
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| {
+        part.len() <= max
+            && part
+                .bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || SHORT_SPECIAL.contains(&c))
+    };
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// `NAME.EXT` of a short name, lower case where the case flags say so
+pub fn short_to_string(short: &[u8; 11], case: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| {
+        bytes
+            .iter()
+            .map(|&c| if lower { c.to_ascii_lowercase() } else { c })
+            .map(char::from)
+            .collect::<String>()
+            .trim_end()
+            .into()
+    };
+    let base: String = part(&short[..8], case & LOWER_BASE != 0);
+    let ext: String = part(&short[8..], case & LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        alloc::format!("{}.{}", base, ext)
+    }
+}
+
+/// Checksum of the short name a long name belongs to
+fn checksum(short: &[u8]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// Byte offsets of the 13 characters in a long name entry
+fn lfn_positions() -> impl Iterator<Item = usize> {
+    (1..11)
+        .step_by(2)
+        .chain((14..26).step_by(2))
+        .chain((28..32).step_by(2))
+}
@@ -0,0 +1,825 @@
+mod dir;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::cell::{Cell, RefCell};
+
+use super::{FileSystem, FileType, FsError, Inode, Stat};
+use crate::{
+    drivers::block::BlockDevice,
+    time::{self, NANOS_PER_SEC},
+};
+use dir::{ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DELETED, DirEntry, ENTRY_SIZE, RawEntry};
+
+const BOOT_SIGNATURE: u16 = 0xaa55;
+/// Volumes with fewer clusters are FAT12, then FAT16 up to `FAT32_MIN_CLUSTERS`
+const FAT16_MIN_CLUSTERS: u32 = 4085;
+const FAT32_MIN_CLUSTERS: u32 = 65525;
+/// Number of the first data cluster, 0 and 1 are reserved
+const FIRST_CLUSTER: u32 = 2;
+const FREE: u32 = 0;
+const ROOT_INO: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Entries from this value up end a chain
+    fn end_of_chain(self) -> u32 {
+        match self {
+            Self::Fat12 => 0xff8,
+            Self::Fat16 => 0xfff8,
+            Self::Fat32 => 0x0fff_fff8,
+        }
+    }
+}
+
+/// FAT12, FAT16 or FAT32 volume. Long names are read and written, the
+/// FAT32 free cluster count in the FSInfo sector is not kept up to date.
+pub struct FatFs {
+    disk: &'static dyn BlockDevice,
+    fat_type: FatType,
+    cluster_size: u64,
+    /// Byte offsets on the disk
+    fat_start: u64,
+    fat_size: u64,
+    fats: u64,
+    /// Fixed root directory of FAT12 and FAT16, empty on FAT32
+    root_start: u64,
+    root_size: u64,
+    root_cluster: u32,
+    data_start: u64,
+    /// Number of data clusters, the last one is `clusters + 1`
+    clusters: u32,
+    /// Where the search for a free cluster starts
+    next_free: Cell<u32>,
+    /// Open files and directories by inode number, so that all users of a
+    /// file share its size and clusters
+    nodes: RefCell<BTreeMap<u64, Weak<FatNode>>>,
+    this: Weak<FatFs>,
+}
+
+pub struct FatNode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    kind: FileType,
+    /// Directory and offset of the short entry in it, None for the root
+    location: Option<(Arc<FatNode>, u64)>,
+    state: RefCell<NodeState>,
+    this: Weak<FatNode>,
+}
+
+struct NodeState {
+    first_cluster: u32,
+    size: u32,
+    attr: u8,
+    mtime: u64,
+    /// Clusters of the chain, read on first use
+    chain: Option<Vec<u32>>,
+    /// The entry is deleted, the clusters are freed when the node is dropped
+    unlinked: bool,
+}
+
+impl FatFs {
+    /// Mounts the volume on `disk`, None if it does not hold one
+    pub fn mount(disk: &'static dyn BlockDevice) -> Option<Arc<Self>> {
+        let block_size = disk.block_size();
+        if disk.block_count() == 0 || block_size < 512 {
+            return None;
+        }
+        let mut boot = vec![0; block_size];
+        disk.read_blocks(0, &mut boot).ok()?;
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap());
+
+        let sector_size = u16_at(11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(14) as u64;
+        let fats = boot[16] as u64;
+        let root_entries = u16_at(17) as u64;
+        let total = match u16_at(19) {
+            0 => u32_at(32) as u64,
+            total => total as u64,
+        };
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36) as u64,
+            sectors => sectors as u64,
+        };
+
+        let valid = matches!(boot[0], 0xeb | 0xe9)
+            && u16_at(510) == BOOT_SIGNATURE
+            && sector_size.is_power_of_two()
+            && (512..=4096).contains(&sector_size)
+            && sector_size.is_multiple_of(block_size as u64)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved > 0
+            && fats > 0
+            && fat_sectors > 0
+            && total * sector_size <= disk.block_count() * block_size as u64;
+        if !valid {
+            return None;
+        }
+
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(sector_size);
+        let data_sector = reserved + fats * fat_sectors + root_sectors;
+        let clusters = total.checked_sub(data_sector)? / sectors_per_cluster;
+        let fat_type = match clusters as u32 {
+            0 => return None,
+            1..FAT16_MIN_CLUSTERS => FatType::Fat12,
+            FAT16_MIN_CLUSTERS..FAT32_MIN_CLUSTERS => FatType::Fat16,
+            _ if root_entries == 0 => FatType::Fat32,
+            _ => return None,
+        };
+
+        Some(Arc::new_cyclic(|this| Self {
+            disk,
+            fat_type,
+            cluster_size: sectors_per_cluster * sector_size,
+            fat_start: reserved * sector_size,
+            fat_size: fat_sectors * sector_size,
+            fats,
+            root_start: (reserved + fats * fat_sectors) * sector_size,
+            root_size: root_sectors * sector_size,
+            root_cluster: if fat_type == FatType::Fat32 {
+                u32_at(44)
+            } else {
+                0
+            },
+            data_start: data_sector * sector_size,
+            clusters: clusters as u32,
+            next_free: Cell::new(FIRST_CLUSTER),
+            nodes: RefCell::new(BTreeMap::new()),
+            this: this.clone(),
+        }))
+    }
+
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let block_size = self.disk.block_size() as u64;
+        let mut block = vec![0; block_size as usize];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % block_size) as usize;
+            let rest = &mut buf[done..];
+            if within == 0 && rest.len() >= block.len() {
+                // whole blocks go straight into the buffer
+                let len = rest.len() - rest.len() % block.len();
+                self.disk.read_blocks(pos / block_size, &mut rest[..len])?;
+                done += len;
+            } else {
+                let len = rest.len().min(block.len() - within);
+                self.disk.read_blocks(pos / block_size, &mut block)?;
+                rest[..len].copy_from_slice(&block[within..within + len]);
+                done += len;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&self, offset: u64, buf: &[u8]) -> Result<(), FsError> {
+        let block_size = self.disk.block_size() as u64;
+        let mut block = vec![0; block_size as usize];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let within = (pos % block_size) as usize;
+            let rest = &buf[done..];
+            if within == 0 && rest.len() >= block.len() {
+                let len = rest.len() - rest.len() % block.len();
+                self.disk.write_blocks(pos / block_size, &rest[..len])?;
+                done += len;
+            } else {
+                // part of a block, the rest of it is kept
+                let len = rest.len().min(block.len() - within);
+                self.disk.read_blocks(pos / block_size, &mut block)?;
+                block[within..within + len].copy_from_slice(&rest[..len]);
+                self.disk.write_blocks(pos / block_size, &block)?;
+                done += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Entry of `cluster` in the FAT
+    fn fat_get(&self, cluster: u32) -> Result<u32, FsError> {
+        let mut bytes = [0; 4];
+        match self.fat_type {
+            FatType::Fat12 => {
+                // 12 bit entries, two of them share three bytes
+                let offset = cluster as u64 * 3 / 2;
+                self.read_bytes(self.fat_start + offset, &mut bytes[..2])?;
+                let entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                Ok(if cluster & 1 == 1 {
+                    entry >> 4
+                } else {
+                    entry & 0xfff
+                } as u32)
+            }
+            FatType::Fat16 => {
+                self.read_bytes(self.fat_start + cluster as u64 * 2, &mut bytes[..2])?;
+                Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+            }
+            FatType::Fat32 => {
+                self.read_bytes(self.fat_start + cluster as u64 * 4, &mut bytes)?;
+                // the top 4 bits are reserved
+                Ok(u32::from_le_bytes(bytes) & 0x0fff_ffff)
+            }
+        }
+    }
+
+    /// Sets the entry of `cluster` in every copy of the FAT
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        for copy in 0..self.fats {
+            let fat = self.fat_start + copy * self.fat_size;
+            let mut bytes = [0; 4];
+            match self.fat_type {
+                FatType::Fat12 => {
+                    let offset = fat + cluster as u64 * 3 / 2;
+                    self.read_bytes(offset, &mut bytes[..2])?;
+                    let old = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    let value = value as u16 & 0xfff;
+                    let entry = if cluster & 1 == 1 {
+                        old & 0x000f | value << 4
+                    } else {
+                        old & 0xf000 | value
+                    };
+                    self.write_bytes(offset, &entry.to_le_bytes())?;
+                }
+                FatType::Fat16 => {
+                    self.write_bytes(fat + cluster as u64 * 2, &(value as u16).to_le_bytes())?;
+                }
+                FatType::Fat32 => {
+                    let offset = fat + cluster as u64 * 4;
+                    self.read_bytes(offset, &mut bytes)?;
+                    let entry = u32::from_le_bytes(bytes) & 0xf000_0000 | value & 0x0fff_ffff;
+                    self.write_bytes(offset, &entry.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.clusters).contains(&cluster)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        debug_assert!(self.is_data_cluster(cluster));
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.cluster_size
+    }
+
+    /// Clusters of the chain starting at `first`
+    fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while self.is_data_cluster(cluster) {
+            // a loop in the FAT
+            if chain.len() >= self.clusters as usize {
+                return Err(FsError::Io);
+            }
+            chain.push(cluster);
+            cluster = self.fat_get(cluster)?;
+        }
+        if cluster != FREE && cluster < self.fat_type.end_of_chain() && !chain.is_empty() {
+            return Err(FsError::Io);
+        }
+        Ok(chain)
+    }
+
+    /// Takes a free cluster, filled with zeroes, and links it after `prev`
+    fn alloc_cluster(&self, prev: Option<u32>) -> Result<u32, FsError> {
+        let last = FIRST_CLUSTER + self.clusters;
+        let start = self.next_free.get().clamp(FIRST_CLUSTER, last - 1);
+        let mut found = None;
+        for cluster in (start..last).chain(FIRST_CLUSTER..start) {
+            if self.fat_get(cluster)? == FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FsError::NoSpace)?;
+
+        self.write_bytes(
+            self.cluster_offset(cluster),
+            &vec![0; self.cluster_size as usize],
+        )?;
+        self.fat_set(cluster, self.fat_type.end_of_chain() | 0x7)?;
+        if let Some(prev) = prev {
+            self.fat_set(prev, cluster)?;
+        }
+        self.next_free.set(cluster + 1);
+        Ok(cluster)
+    }
+
+    fn free_chain(&self, chain: &[u32]) -> Result<(), FsError> {
+        for &cluster in chain {
+            self.fat_set(cluster, FREE)?;
+        }
+        if let Some(&first) = chain.first() {
+            self.next_free.set(self.next_free.get().min(first));
+        }
+        Ok(())
+    }
+
+    /// Node of the file or directory `entry` of `dir`, shared with the other
+    /// users of it
+    fn node(&self, dir: &FatNode, entry: &DirEntry) -> Result<Arc<FatNode>, FsError> {
+        let ino = dir.disk_offset(entry.offset)? / ENTRY_SIZE as u64;
+        let mut nodes = self.nodes.borrow_mut();
+        if let Some(node) = nodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(node);
+        }
+
+        let kind = if entry.raw.attr() & ATTR_DIRECTORY != 0 {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        };
+        let node = Arc::new_cyclic(|this| FatNode {
+            fs: self.this.upgrade().unwrap(),
+            ino,
+            kind,
+            location: Some((dir.this.upgrade().unwrap(), entry.offset)),
+            state: RefCell::new(NodeState {
+                first_cluster: entry.raw.first_cluster(),
+                size: entry.raw.size(),
+                attr: entry.raw.attr(),
+                mtime: entry.raw.mtime(),
+                chain: None,
+                unlinked: false,
+            }),
+            this: this.clone(),
+        });
+        nodes.retain(|_, node| node.strong_count() > 0);
+        nodes.insert(ino, Arc::downgrade(&node));
+        Ok(node)
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &str {
+        match self.fat_type {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        let mut nodes = self.nodes.borrow_mut();
+        if let Some(root) = nodes.get(&ROOT_INO).and_then(Weak::upgrade) {
+            return root;
+        }
+
+        let root = Arc::new_cyclic(|this| FatNode {
+            fs: self.this.upgrade().unwrap(),
+            ino: ROOT_INO,
+            kind: FileType::Directory,
+            location: None,
+            state: RefCell::new(NodeState {
+                first_cluster: self.root_cluster,
+                size: 0,
+                attr: ATTR_DIRECTORY,
+                mtime: 0,
+                chain: None,
+                unlinked: false,
+            }),
+            this: this.clone(),
+        });
+        nodes.insert(ROOT_INO, Arc::downgrade(&root));
+        root
+    }
+}
+
+impl FatNode {
+    /// FAT12 and FAT16 keep the root directory outside of the clusters
+    fn is_fixed_root(&self) -> bool {
+        self.location.is_none() && self.fs.fat_type != FatType::Fat32
+    }
+
+    /// Reads the cluster chain if it is not known yet
+    fn load_chain(&self) -> Result<(), FsError> {
+        let mut state = self.state.borrow_mut();
+        if state.chain.is_none() {
+            state.chain = Some(self.fs.chain(state.first_cluster)?);
+        }
+        Ok(())
+    }
+
+    /// Bytes of storage the node has, whole clusters
+    fn capacity(&self) -> Result<u64, FsError> {
+        if self.is_fixed_root() {
+            return Ok(self.fs.root_size);
+        }
+        self.load_chain()?;
+        let chain_len = self.state.borrow().chain.as_ref().unwrap().len() as u64;
+        Ok(chain_len * self.fs.cluster_size)
+    }
+
+    /// Disk offset of byte `offset` of the node, which must be below the capacity
+    fn disk_offset(&self, offset: u64) -> Result<u64, FsError> {
+        if self.is_fixed_root() {
+            return Ok(self.fs.root_start + offset);
+        }
+        self.load_chain()?;
+        let state = self.state.borrow();
+        let cluster = state.chain.as_ref().unwrap()[(offset / self.fs.cluster_size) as usize];
+        Ok(self.fs.cluster_offset(cluster) + offset % self.fs.cluster_size)
+    }
+
+    /// Reads the storage of the node, ignoring the file size
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let len = (buf.len() - done).min(self.contiguous(pos) as usize);
+            self.fs
+                .read_bytes(self.disk_offset(pos)?, &mut buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Writes the storage of the node, adding clusters as needed
+    fn write_data(&self, offset: u64, buf: &[u8]) -> Result<(), FsError> {
+        self.reserve(offset + buf.len() as u64)?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let len = (buf.len() - done).min(self.contiguous(pos) as usize);
+            self.fs
+                .write_bytes(self.disk_offset(pos)?, &buf[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Bytes from `offset` to the end of its cluster
+    fn contiguous(&self, offset: u64) -> u64 {
+        if self.is_fixed_root() {
+            self.fs.root_size - offset
+        } else {
+            self.fs.cluster_size - offset % self.fs.cluster_size
+        }
+    }
+
+    /// Grows the chain to at least `size` bytes
+    fn reserve(&self, size: u64) -> Result<(), FsError> {
+        if self.is_fixed_root() {
+            return if size <= self.fs.root_size {
+                Ok(())
+            } else {
+                Err(FsError::NoSpace)
+            };
+        }
+
+        while self.capacity()? < size {
+            let last = self.state.borrow().chain.as_ref().unwrap().last().copied();
+            let cluster = self.fs.alloc_cluster(last)?;
+            let mut state = self.state.borrow_mut();
+            state.chain.as_mut().unwrap().push(cluster);
+            if last.is_none() {
+                state.first_cluster = cluster;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the size, first cluster and time back to the directory entry
+    fn sync_entry(&self) -> Result<(), FsError> {
+        let Some((dir, offset)) = &self.location else {
+            return Ok(());
+        };
+        let state = self.state.borrow();
+        if state.unlinked {
+            return Ok(());
+        }
+
+        let mut raw = RawEntry([0; ENTRY_SIZE]);
+        dir.read_data(*offset, &mut raw.0)?;
+        raw.set_first_cluster(state.first_cluster);
+        raw.set_size(if self.kind == FileType::Directory {
+            0
+        } else {
+            state.size
+        });
+        raw.set_mtime(state.mtime);
+        dir.write_data(*offset, &raw.0)
+    }
+
+    fn touch(&self) {
+        self.state.borrow_mut().mtime = time::realtime() / NANOS_PER_SEC;
+    }
+
+    /// Entries of the directory
+    fn entries(&self) -> Result<Vec<DirEntry>, FsError> {
+        let mut data = vec![0; self.capacity()? as usize];
+        self.read_data(0, &mut data)?;
+        Ok(dir::parse(&data))
+    }
+
+    /// Entry `name`, the long or the short name of it. Names match without
+    /// regard to case as on other systems.
+    fn find(&self, name: &str) -> Result<DirEntry, FsError> {
+        self.entries()?
+            .into_iter()
+            .find(|entry| {
+                entry.name.eq_ignore_ascii_case(name)
+                    || dir::short_to_string(&entry.short, 0).eq_ignore_ascii_case(name)
+            })
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Offset of `count` consecutive free entries, the directory grows if
+    /// there are not enough
+    fn free_slots(&self, count: usize) -> Result<u64, FsError> {
+        let capacity = self.capacity()?;
+        let mut data = vec![0; capacity as usize];
+        self.read_data(0, &mut data)?;
+
+        let mut run = 0;
+        for (i, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            if raw[0] == DELETED || raw[0] == 0 {
+                run += 1;
+                if run == count {
+                    return Ok(((i + 1 - count) * ENTRY_SIZE) as u64);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        // the new clusters are zeroed, the free run continues into them
+        self.reserve(capacity + ((count - run) * ENTRY_SIZE) as u64)?;
+        Ok(capacity - (run * ENTRY_SIZE) as u64)
+    }
+
+    /// Adds the entry for `name`, its entries and the offset of the short one
+    fn add_entry(&self, name: &str, raw: RawEntry) -> Result<u64, FsError> {
+        let entries = self.entries()?;
+        let taken = entries.iter().map(|entry| entry.short).collect::<Vec<_>>();
+        let short = dir::short_name(name, &taken).ok_or(FsError::Exists)?;
+
+        let raws = dir::build(name, short, raw);
+        let offset = self.free_slots(raws.len())?;
+        let bytes = raws.iter().flat_map(|raw| raw.0).collect::<Vec<_>>();
+        self.write_data(offset, &bytes)?;
+        Ok(offset + ((raws.len() - 1) * ENTRY_SIZE) as u64)
+    }
+
+    /// Deletes the entry `name`, a directory must be empty
+    fn remove(&self, name: &str, kind: FileType) -> Result<(), FsError> {
+        let entry = self.find(name)?;
+        let node = self.fs.node(self, &entry)?;
+        match (kind, node.kind) {
+            (FileType::Directory, FileType::Directory) => {
+                if node
+                    .entries()?
+                    .iter()
+                    .any(|e| e.name != "." && e.name != "..")
+                {
+                    return Err(FsError::NotEmpty);
+                }
+            }
+            (_, FileType::Directory) => return Err(FsError::IsADirectory),
+            (FileType::Directory, _) => return Err(FsError::NotADirectory),
+            _ => {}
+        }
+
+        for offset in (entry.first..=entry.offset).step_by(ENTRY_SIZE) {
+            self.write_data(offset, &[DELETED])?;
+        }
+        self.fs.nodes.borrow_mut().remove(&node.ino);
+        node.state.borrow_mut().unlinked = true;
+        self.touch();
+        self.sync_entry()
+    }
+
+    /// Frees the clusters after the first `size` bytes
+    fn shrink(&self, size: u64) -> Result<(), FsError> {
+        self.load_chain()?;
+        let keep = size.div_ceil(self.fs.cluster_size) as usize;
+        let mut state = self.state.borrow_mut();
+        let chain = state.chain.as_mut().unwrap();
+        if keep >= chain.len() {
+            return Ok(());
+        }
+
+        let freed = chain.split_off(keep);
+        match chain.last() {
+            Some(&last) => self
+                .fs
+                .fat_set(last, self.fs.fat_type.end_of_chain() | 0x7)?,
+            None => state.first_cluster = FREE,
+        }
+        self.fs.free_chain(&freed)
+    }
+}
+
+impl Drop for FatNode {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        if !state.unlinked {
+            return;
+        }
+        // nothing can be done about errors here, the clusters stay lost
+        let chain = match state.chain.take() {
+            Some(chain) => Ok(chain),
+            None => self.fs.chain(state.first_cluster),
+        };
+        if let Ok(chain) = chain {
+            let _ = self.fs.free_chain(&chain);
+        }
+    }
+}
+
+impl Inode for FatNode {
+    fn stat(&self) -> Stat {
+        let size = match self.kind {
+            FileType::Directory => self.capacity().unwrap_or(0),
+            _ => self.state.borrow().size as u64,
+        };
+        let state = self.state.borrow();
+        let writable = if state.attr & ATTR_READ_ONLY != 0 {
+            0
+        } else {
+            0o222
+        };
+        Stat {
+            ino: self.ino,
+            kind: self.kind,
+            mode: (0o555 | writable) as u16,
+            nlink: 1,
+            size,
+            mtime: state.mtime,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let size = self.state.borrow().size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((size - offset) as usize);
+        self.read_data(offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        if self.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let end = offset + buf.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        // a hole before the data reads as zeroes
+        let size = self.state.borrow().size as u64;
+        if offset > size {
+            self.write_data(size, &vec![0; (offset - size) as usize])?;
+        }
+        self.write_data(offset, buf)?;
+
+        let mut state = self.state.borrow_mut();
+        state.size = state.size.max(end as u32);
+        drop(state);
+        self.touch();
+        self.sync_entry()?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if self.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if size > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+
+        let old = self.state.borrow().size as u64;
+        if size < old {
+            self.shrink(size)?;
+        } else if size > old {
+            self.write_data(old, &vec![0; (size - old) as usize])?;
+        }
+        self.state.borrow_mut().size = size as u32;
+        self.touch();
+        self.sync_entry()
+    }
+
+    fn read_dir(&self) -> Result<Vec<super::DirEntry>, FsError> {
+        if self.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        let mut entries = Vec::new();
+        for entry in self.entries()? {
+            // the dot entries are numbered like the directories they stand for
+            let ino = match entry.name.as_str() {
+                "." => self.ino,
+                ".." => self.location.as_ref().map_or(ROOT_INO, |(dir, _)| dir.ino),
+                _ => self.disk_offset(entry.offset)? / ENTRY_SIZE as u64,
+            };
+            let kind = if entry.raw.attr() & ATTR_DIRECTORY != 0 {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            };
+            entries.push(super::DirEntry {
+                name: entry.name,
+                ino,
+                kind,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if self.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let entry = self.find(name)?;
+        Ok(self.fs.node(self, &entry)?)
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        if self.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        if !dir::is_valid_name(name) {
+            return Err(FsError::InvalidPath);
+        }
+        if self.find(name).is_ok() {
+            return Err(FsError::Exists);
+        }
+
+        let now = time::realtime() / NANOS_PER_SEC;
+        let raw = match kind {
+            FileType::Regular => RawEntry::new([0; 11], ATTR_ARCHIVE, FREE, now),
+            FileType::Directory => {
+                // . and .. come first, .. is cluster 0 for the root
+                let cluster = self.fs.alloc_cluster(None)?;
+                let parent = match self.location {
+                    Some(_) => self.state.borrow().first_cluster,
+                    None => FREE,
+                };
+                let dots = [
+                    RawEntry::new(*b".          ", ATTR_DIRECTORY, cluster, now),
+                    RawEntry::new(*b"..         ", ATTR_DIRECTORY, parent, now),
+                ];
+                let bytes = dots.iter().flat_map(|raw| raw.0).collect::<Vec<_>>();
+                if let Err(err) = self.fs.write_bytes(self.fs.cluster_offset(cluster), &bytes) {
+                    self.fs.free_chain(&[cluster])?;
+                    return Err(err);
+                }
+                RawEntry::new([0; 11], ATTR_DIRECTORY, cluster, now)
+            }
+            FileType::CharDevice | FileType::Symlink => return Err(FsError::Unsupported),
+        };
+
+        let offset = match self.add_entry(name, raw) {
+            Ok(offset) => offset,
+            Err(err) => {
+                if kind == FileType::Directory {
+                    self.fs.free_chain(&[raw.first_cluster()])?;
+                }
+                return Err(err);
+            }
+        };
+        self.touch();
+        self.sync_entry()?;
+
+        let entry = DirEntry {
+            name: String::from(name),
+            short: [0; 11],
+            raw,
+            offset,
+            first: offset,
+        };
+        Ok(self.fs.node(self, &entry)?)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        self.remove(name, FileType::Regular)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        self.remove(name, FileType::Directory)
+    }
+}
@@ -0,0 +1,184 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::cell::Cell;
+
+use bitflags::bitflags;
+
+use super::{DirEntry, FileType, FsError, Inode, Stat, devfs};
+
+/// Open files per process
+pub const MAX_FILES: usize = 32;
+
+pub type Fd = u32;
+
+bitflags! {
+    /// open flags, the values are the ones of Linux
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags: u32 {
+        /// Without WRONLY or RDWR the file is opened read only
+        const WRONLY = 0o1;
+        const RDWR = 0o2;
+        const CREAT = 0o100;
+        /// With CREAT, fail if the file exists
+        const EXCL = 0o200;
+        const TRUNC = 0o1000;
+        /// Every write goes to the end of the file
+        const APPEND = 0o2000;
+        /// Reads return 0 instead of waiting for input
+        const NONBLOCK = 0o4000;
+        const DIRECTORY = 0o200000;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Open file. Forked processes share it, and with it the offset.
+pub struct File {
+    inode: Arc<dyn Inode>,
+    flags: OpenFlags,
+    offset: Cell<u64>,
+}
+
+/// File descriptors of a process, they index the table
+#[derive(Clone)]
+pub struct FdTable {
+    files: Vec<Option<Arc<File>>>,
+}
+
+impl OpenFlags {
+    pub fn is_readable(self) -> bool {
+        !self.contains(Self::WRONLY)
+    }
+
+    pub fn is_writable(self) -> bool {
+        self.intersects(Self::WRONLY | Self::RDWR)
+    }
+}
+
+impl File {
+    pub fn new(inode: Arc<dyn Inode>, flags: OpenFlags) -> Self {
+        Self {
+            inode,
+            flags,
+            offset: Cell::new(0),
+        }
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.is_readable() {
+            return Err(FsError::BadFd);
+        }
+
+        let offset = self.offset.get();
+        let count = if self.flags.contains(OpenFlags::NONBLOCK) {
+            self.inode.read_nonblock(offset, buf)?
+        } else {
+            self.inode.read_at(offset, buf)?
+        };
+        self.offset.set(offset + count as u64);
+        Ok(count)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.is_writable() {
+            return Err(FsError::BadFd);
+        }
+
+        let offset = if self.flags.contains(OpenFlags::APPEND) {
+            self.inode.stat().size
+        } else {
+            self.offset.get()
+        };
+        let count = self.inode.write_at(offset, buf)?;
+        self.offset.set(offset + count as u64);
+        Ok(count)
+    }
+
+    /// Moves the offset, returns the new one. It may go past the end of the file.
+    pub fn seek(&self, pos: SeekFrom) -> Result<u64, FsError> {
+        let stat = self.inode.stat();
+        let offset = match pos {
+            _ if stat.kind == FileType::CharDevice => return Err(FsError::Unsupported),
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.offset.get().checked_add_signed(delta),
+            SeekFrom::End(delta) => stat.size.checked_add_signed(delta),
+        };
+
+        let offset = offset.ok_or(FsError::InvalidArgs)?;
+        self.offset.set(offset);
+        Ok(offset)
+    }
+
+    /// Cuts the file to `size` bytes or extends it with zeroes
+    pub fn truncate(&self, size: u64) -> Result<(), FsError> {
+        if !self.flags.is_writable() {
+            return Err(FsError::BadFd);
+        }
+        self.inode.truncate(size)
+    }
+
+    pub fn stat(&self) -> Stat {
+        self.inode.stat()
+    }
+
+    /// Entries of a directory from the offset on, which counts entries.
+    /// `fill` gets them with the offset after each until it returns false,
+    /// the offset then points at the first one it did not take.
+    pub fn read_dir(&self, mut fill: impl FnMut(&DirEntry, u64) -> bool) -> Result<(), FsError> {
+        if !self.flags.is_readable() {
+            return Err(FsError::BadFd);
+        }
+
+        let mut offset = self.offset.get();
+        for entry in self.inode.read_dir()?.iter().skip(offset as usize) {
+            if !fill(entry, offset + 1) {
+                break;
+            }
+            offset += 1;
+        }
+        self.offset.set(offset);
+        Ok(())
+    }
+}
+
+impl FdTable {
+    /// Standard input, output and error on the terminal of the process
+    pub fn console() -> Self {
+        let console = Arc::new(File::new(devfs::console(), OpenFlags::RDWR));
+        Self {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    pub fn get(&self, fd: Fd) -> Option<&Arc<File>> {
+        self.files.get(fd as usize)?.as_ref()
+    }
+
+    /// Gives `file` the lowest free descriptor, None if all `MAX_FILES` are used
+    pub fn insert(&mut self, file: File) -> Option<Fd> {
+        let file = Some(Arc::new(file));
+        match self.files.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.files[fd] = file;
+                Some(fd as _)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(file);
+                Some((self.files.len() - 1) as _)
+            }
+            None => None,
+        }
+    }
+
+    pub fn close(&mut self, fd: Fd) -> Option<Arc<File>> {
+        self.files.get_mut(fd as usize)?.take()
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
@@ -0,0 +1,498 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    cell::{Cell, RefCell},
+    str,
+};
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+
+const TAR_BLOCK: usize = 512;
+/// "ustar\0" of POSIX archives and "ustar  " of GNU ones
+const TAR_MAGIC: &[u8] = b"ustar";
+const CPIO_MAGIC: &[u8] = b"070701";
+/// Same as `CPIO_MAGIC`, with a checksum that is not checked
+const CPIO_CRC_MAGIC: &[u8] = b"070702";
+const CPIO_HEADER: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+/// File types of the cpio mode
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Read only file system of an archive in memory, the initial ramdisk. Files
+/// point into the archive, only the directory tree is built on the heap.
+/// Devices, pipes and sockets in it are left out.
+pub struct InitrdFs {
+    format: &'static str,
+    root: Arc<Node>,
+}
+
+struct Node {
+    ino: u64,
+    kind: FileType,
+    mode: u16,
+    mtime: u64,
+    nlink: Cell<u32>,
+    /// Content of a file
+    data: Cell<&'static [u8]>,
+    /// Target of a symbolic link
+    target: String,
+    entries: RefCell<BTreeMap<String, Arc<Node>>>,
+}
+
+/// Member of an archive
+struct Member {
+    path: String,
+    kind: FileType,
+    mode: u16,
+    mtime: u64,
+    data: &'static [u8],
+    target: String,
+    /// Earlier path of the same file
+    link: Option<String>,
+}
+
+/// Builds the tree while the archive is read
+struct Builder {
+    root: Arc<Node>,
+    /// Last inode number given out
+    ino: u64,
+}
+
+impl InitrdFs {
+    /// Reads the ustar or newc cpio archive `data`, None if it is neither
+    pub fn new(data: &'static [u8]) -> Option<Arc<Self>> {
+        let mut builder = Builder::new();
+        let format = if is_tar(data) {
+            read_tar(data, &mut builder)?;
+            "ustar"
+        } else if data.starts_with(CPIO_MAGIC) || data.starts_with(CPIO_CRC_MAGIC) {
+            read_cpio(data, &mut builder)?;
+            "cpio"
+        } else {
+            return None;
+        };
+
+        Some(Arc::new(Self {
+            format,
+            root: builder.root,
+        }))
+    }
+}
+
+impl FileSystem for InitrdFs {
+    fn name(&self) -> &str {
+        self.format
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl Node {
+    fn new(ino: u64, kind: FileType, mode: u16, mtime: u64) -> Self {
+        Self {
+            ino,
+            kind,
+            mode,
+            mtime,
+            nlink: Cell::new(1),
+            data: Cell::new(&[]),
+            target: String::new(),
+            entries: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn check_dir(&self) -> Result<(), FsError> {
+        match self.kind {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+impl Inode for Node {
+    fn stat(&self) -> Stat {
+        // directories are linked from their parent, themselves and subdirectories
+        let nlink = match self.kind {
+            FileType::Directory => {
+                let entries = self.entries.borrow();
+                2 + entries
+                    .values()
+                    .filter(|node| node.kind == FileType::Directory)
+                    .count() as u32
+            }
+            _ => self.nlink.get(),
+        };
+        Stat {
+            ino: self.ino,
+            kind: self.kind,
+            mode: self.mode,
+            nlink,
+            size: match self.kind {
+                FileType::Symlink => self.target.len(),
+                _ => self.data.get().len(),
+            } as u64,
+            mtime: self.mtime,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.kind != FileType::Regular {
+            return Err(self.unsupported());
+        }
+        let data = self.data.get();
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+
+        let rest = &data[offset as usize..];
+        let len = buf.len().min(rest.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        match self.kind {
+            FileType::Regular => Err(FsError::ReadOnly),
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        self.write_at(0, &[]).map(|_| ())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_dir()?;
+        Ok(self
+            .entries
+            .borrow()
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                ino: node.ino,
+                kind: node.kind,
+            })
+            .collect())
+    }
+
+    fn readlink(&self) -> Result<String, FsError> {
+        if self.kind != FileType::Symlink {
+            return Err(FsError::InvalidArgs);
+        }
+        Ok(self.target.clone())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        match self.entries.borrow().get(name) {
+            Some(node) => Ok(node.clone()),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&self, _name: &str, _dir: &Arc<dyn Inode>, _new_name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        Err(FsError::ReadOnly)
+    }
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            root: Arc::new(Node::new(1, FileType::Directory, 0o755, 0)),
+            ino: 1,
+        }
+    }
+
+    /// Adds `member` and the directories leading to it that the archive
+    /// does not list. Members that appear twice replace the earlier one.
+    fn add(&mut self, member: Member) {
+        let mut names = member
+            .path
+            .split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .collect::<Vec<_>>();
+        let Some(name) = names.pop() else {
+            // the root itself, as "./"
+            return;
+        };
+        if name == ".." || names.contains(&"..") {
+            return;
+        }
+
+        let mut dir = self.root.clone();
+        for name in names {
+            let next = dir.entries.borrow().get(name).cloned();
+            dir = match next {
+                Some(node) if node.kind == FileType::Directory => node,
+                // a file in the way, the member is dropped
+                Some(_) => return,
+                None => {
+                    self.ino += 1;
+                    let node = Arc::new(Node::new(
+                        self.ino,
+                        FileType::Directory,
+                        0o755,
+                        member.mtime,
+                    ));
+                    dir.entries
+                        .borrow_mut()
+                        .insert(name.to_string(), node.clone());
+                    node
+                }
+            };
+        }
+
+        let node = match &member.link {
+            Some(target) => match self.find(target) {
+                Some(node) if node.kind != FileType::Directory => {
+                    node.nlink.set(node.nlink.get() + 1);
+                    node
+                }
+                _ => return,
+            },
+            None => {
+                // a directory listed after its content keeps the content
+                let existing = dir.entries.borrow().get(name).cloned();
+                match existing {
+                    Some(node) if node.kind == FileType::Directory && member.kind == node.kind => {
+                        return;
+                    }
+                    _ => {}
+                }
+                self.ino += 1;
+                let mut node = Node::new(self.ino, member.kind, member.mode, member.mtime);
+                node.data.set(member.data);
+                node.target = member.target;
+                Arc::new(node)
+            }
+        };
+        dir.entries.borrow_mut().insert(name.to_string(), node);
+    }
+
+    /// Node added at the archive `path`
+    fn find(&self, path: &str) -> Option<Arc<Node>> {
+        path.split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .try_fold(self.root.clone(), |dir, name| {
+                dir.entries.borrow().get(name).cloned()
+            })
+    }
+}
+
+fn is_tar(data: &[u8]) -> bool {
+    data.len() >= TAR_BLOCK && data[257..].starts_with(TAR_MAGIC)
+}
+
+/// Reads a ustar archive, GNU long names and pax paths included
+fn read_tar(data: &'static [u8], builder: &mut Builder) -> Option<()> {
+    let mut offset = 0;
+    // names for the next member from GNU or pax extension headers
+    let mut long_path = None;
+    let mut long_link = None;
+
+    // two zero blocks end the archive, one is enough to stop
+    while let Some(header) = data.get(offset..offset + TAR_BLOCK)
+        && header.iter().any(|&byte| byte != 0)
+    {
+        if !header[257..].starts_with(TAR_MAGIC) || !tar_checksum_ok(header) {
+            return None;
+        }
+        let size = octal(&header[124..136])? as usize;
+        let body = data.get(offset + TAR_BLOCK..offset + TAR_BLOCK + size)?;
+        offset += TAR_BLOCK + size.next_multiple_of(TAR_BLOCK);
+
+        let (kind, link) = match header[156] {
+            b'0' | 0 | b'7' => (FileType::Regular, None),
+            b'1' => (FileType::Regular, Some(cstr(&header[157..257]))),
+            b'2' => (FileType::Symlink, None),
+            b'5' => (FileType::Directory, None),
+            b'L' => {
+                long_path = Some(cstr(body));
+                continue;
+            }
+            b'K' => {
+                long_link = Some(cstr(body));
+                continue;
+            }
+            b'x' => {
+                for (key, value) in pax_records(body) {
+                    match key {
+                        "path" => long_path = Some(value),
+                        "linkpath" => long_link = Some(value),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            // devices, pipes and global pax headers
+            _ => {
+                long_path = None;
+                long_link = None;
+                continue;
+            }
+        };
+
+        let path = long_path.take().unwrap_or_else(|| {
+            let (prefix, name) = (cstr(&header[345..500]), cstr(&header[..100]));
+            match prefix.is_empty() {
+                true => name,
+                false => alloc::format!("{}/{}", prefix, name),
+            }
+        });
+        let target = long_link.take().unwrap_or_else(|| cstr(&header[157..257]));
+        builder.add(Member {
+            path,
+            kind,
+            mode: octal(&header[100..108])? as u16 & 0o7777,
+            mtime: octal(&header[136..148])?,
+            // hard links carry no data
+            data: if link.is_some() { &[] } else { body },
+            link: link.map(|_| target.clone()),
+            target,
+        });
+    }
+    Some(())
+}
+
+/// The checksum is the sum of the header bytes with the checksum field as spaces
+fn tar_checksum_ok(header: &[u8]) -> bool {
+    let sum = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| match i {
+            148..156 => b' ' as u64,
+            _ => byte as u64,
+        })
+        .sum::<u64>();
+    octal(&header[148..156]) == Some(sum)
+}
+
+/// Records "<length> <key>=<value>\n" of a pax extended header
+fn pax_records(body: &[u8]) -> impl Iterator<Item = (&str, String)> {
+    let mut rest = body;
+    core::iter::from_fn(move || {
+        let space = rest.iter().position(|&c| c == b' ')?;
+        let len = str::from_utf8(&rest[..space]).ok()?.parse::<usize>().ok()?;
+        let record = rest.get(space + 1..len)?.strip_suffix(b"\n")?;
+        rest = &rest[len..];
+        let eq = record.iter().position(|&c| c == b'=')?;
+        let key = str::from_utf8(&record[..eq]).ok()?;
+        Some((key, String::from_utf8_lossy(&record[eq + 1..]).into_owned()))
+    })
+}
+
+/// Reads a newc cpio archive
+fn read_cpio(data: &'static [u8], builder: &mut Builder) -> Option<()> {
+    let mut offset = 0;
+    // hard linked files share the inode number, the data is on the last one
+    let mut links: BTreeMap<(u32, u32, u32), String> = BTreeMap::new();
+
+    loop {
+        let header = data.get(offset..offset + CPIO_HEADER)?;
+        if !header.starts_with(CPIO_MAGIC) && !header.starts_with(CPIO_CRC_MAGIC) {
+            return None;
+        }
+        // ino, mode, uid, gid, nlink, mtime, size, the device numbers and
+        // the name size, 8 hex digits each after the magic
+        let field = |i: usize| hex(&header[6 + i * 8..14 + i * 8]);
+        let (ino, mode, nlink, mtime, size) =
+            (field(0)?, field(1)?, field(4)?, field(5)?, field(6)?);
+        let (dev_major, dev_minor, name_size) = (field(7)?, field(8)?, field(11)? as usize);
+
+        let name_start = offset + CPIO_HEADER;
+        let path = cstr(data.get(name_start..name_start + name_size)?);
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let body = data.get(data_start..data_start + size as usize)?;
+        offset = (data_start + size as usize).next_multiple_of(4);
+        if path == CPIO_TRAILER {
+            return Some(());
+        }
+
+        let kind = match mode & S_IFMT {
+            S_IFREG => FileType::Regular,
+            S_IFDIR => FileType::Directory,
+            S_IFLNK => FileType::Symlink,
+            _ => continue,
+        };
+        let mut link = None;
+        if kind == FileType::Regular && nlink > 1 {
+            let key = (dev_major, dev_minor, ino);
+            match links.get(&key) {
+                // the first name gets the data when it comes with a later one
+                Some(first) if !body.is_empty() => {
+                    if let Some(node) = builder.find(first) {
+                        node.data.set(body);
+                    }
+                    link = Some(first.clone());
+                }
+                Some(first) => link = Some(first.clone()),
+                None => {
+                    links.insert(key, path.clone());
+                }
+            }
+        }
+
+        builder.add(Member {
+            path,
+            kind,
+            mode: (mode & 0o7777) as u16,
+            mtime: mtime as u64,
+            data: body,
+            target: match kind {
+                FileType::Symlink => String::from_utf8_lossy(body).into_owned(),
+                _ => String::new(),
+            },
+            link,
+        });
+    }
+}
+
+/// NUL terminated or NUL padded string of a header
+fn cstr(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Octal number of a tar header, padded with spaces or NULs
+fn octal(bytes: &[u8]) -> Option<u64> {
+    let digits = str::from_utf8(bytes).ok()?.trim_matches([' ', '\0']);
+    match digits {
+        "" => Some(0),
+        digits => u64::from_str_radix(digits, 8).ok(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> Option<u32> {
+    u32::from_str_radix(str::from_utf8(bytes).ok()?, 16).ok()
+}
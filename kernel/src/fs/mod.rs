@@ -0,0 +1,393 @@
+// Inodes and open files are shared through `Arc`s but hold plain cells: the
+// kernel runs on one CPU and file system calls do not run in interrupts
+#![allow(clippy::arc_with_non_send_sync)]
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+mod file;
+pub mod initrd;
+pub mod tmpfs;
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+
+use utils::nullsync;
+
+use crate::{
+    boot_info::boot_info,
+    device_manager::DEVICES,
+    drivers::block::{BlockDevice, BlockError, Partition},
+    paging::{FRAMES, PAGE_SIZE},
+};
+
+pub use file::{FdTable, File, OpenFlags, SeekFrom};
+
+static MOUNTS: nullsync::RefCell<Vec<Mount>> = nullsync::RefCell::new(Vec::new());
+
+/// Symbolic links followed while resolving one path, more mean a loop
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    Exists,
+    /// The file system or the file can not be written
+    ReadOnly,
+    NoSpace,
+    /// Only empty directories can be removed
+    NotEmpty,
+    /// The path is empty, relative or not UTF-8
+    InvalidPath,
+    InvalidArgs,
+    /// The file is not open for reading or writing
+    BadFd,
+    /// The operation makes no sense for the file, like seeking a terminal
+    Unsupported,
+    /// The device failed or the data on it is inconsistent
+    Io,
+    /// Resolving the path followed more than `MAX_SYMLINKS` symbolic links
+    SymlinkLoop,
+    /// A link or rename between two file systems
+    CrossDevice,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    CharDevice,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub ino: u64,
+    pub kind: FileType,
+    /// Permission bits, nothing checks them yet
+    pub mode: u16,
+    pub nlink: u32,
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub mtime: u64,
+}
+
+/// Entry of a directory listing
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+/// File, directory or device of a mounted file system. Every operation but
+/// `stat` fails by default, file systems implement what they support.
+pub trait Inode {
+    fn stat(&self) -> Stat;
+
+    /// Reads from `offset` on, returns the number of bytes read, 0 at the end
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(self.unsupported())
+    }
+
+    /// Like `read_at`, but returns 0 instead of waiting for input
+    fn read_nonblock(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.read_at(offset, buf)
+    }
+
+    /// Writes at `offset`, growing the file if it ends before
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(self.unsupported())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(self.unsupported())
+    }
+
+    /// Entries of a directory, with `.` and `..` if the file system stores them
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Target of a symbolic link
+    fn readlink(&self) -> Result<String, FsError> {
+        Err(FsError::InvalidArgs)
+    }
+
+    /// Entry `name` of a directory
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Adds the entry `name` to a directory
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Removes the entry `name` of a directory, it must not be a directory
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Removes the empty directory `name` from a directory
+    fn rmdir(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Adds the entry `name` for `inode`, a file of the same file system, to
+    /// a directory
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Moves the entry `name` of a directory to `new_name` in `dir`, which is
+    /// of the same file system. What `new_name` was is removed, a directory
+    /// only replaces an empty directory.
+    fn rename(&self, _name: &str, _dir: &Arc<dyn Inode>, _new_name: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Error of the operations the file does not implement
+    fn unsupported(&self) -> FsError {
+        match self.stat().kind {
+            FileType::Directory => FsError::IsADirectory,
+            FileType::Regular | FileType::CharDevice | FileType::Symlink => FsError::Unsupported,
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> Self {
+        FsError::Io
+    }
+}
+
+pub trait FileSystem {
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+struct Mount {
+    /// Normalized path components of the mount point
+    path: Vec<String>,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Makes `fs` visible at `path`, hiding whatever was there. The mount point
+/// does not need to exist in the file system below.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = components(path)?
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut mounts = MOUNTS.borrow_mut();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(FsError::Exists);
+    }
+    crate::info!("{} mounted at /{}", fs.name(), path.join("/"));
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// Inode at the absolute `path`, symbolic links are followed
+pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, FsError> {
+    walk(path).map(|(inode, _)| inode)
+}
+
+/// Directory containing the absolute `path` and the last component of it,
+/// which is not followed if it is a symbolic link
+pub fn lookup_parent(path: &str) -> Result<(Arc<dyn Inode>, &str), FsError> {
+    walk_parent(path).map(|(dir, name, _)| (dir, name))
+}
+
+/// Opens the file at `path`, creating it with `OpenFlags::CREAT`
+pub fn open(path: &str, flags: OpenFlags) -> Result<File, FsError> {
+    let inode = match lookup(path) {
+        Ok(_) if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) => {
+            return Err(FsError::Exists);
+        }
+        Ok(inode) => inode,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREAT) => {
+            let (parent, name) = lookup_parent(path)?;
+            parent.create(name, FileType::Regular)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let kind = inode.stat().kind;
+    if kind == FileType::Directory && flags.is_writable() {
+        return Err(FsError::IsADirectory);
+    }
+    if kind != FileType::Directory && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(FsError::NotADirectory);
+    }
+    if kind == FileType::Regular && flags.is_writable() && flags.contains(OpenFlags::TRUNC) {
+        inode.truncate(0)?;
+    }
+    Ok(File::new(inode, flags))
+}
+
+pub fn mkdir(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.create(name, FileType::Directory).map(|_| ())
+}
+
+pub fn unlink(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.unlink(name)
+}
+
+pub fn rmdir(path: &str) -> Result<(), FsError> {
+    let (parent, name) = lookup_parent(path)?;
+    parent.rmdir(name)
+}
+
+/// Gives the file at `old` the second name `new`, on the same file system
+pub fn link(old: &str, new: &str) -> Result<(), FsError> {
+    let (parent, name, fs) = walk_parent(old)?;
+    let inode = parent.lookup(name)?;
+    let (new_parent, new_name, new_fs) = walk_parent(new)?;
+    if !Arc::ptr_eq(&fs, &new_fs) {
+        return Err(FsError::CrossDevice);
+    }
+    new_parent.link(new_name, &inode)
+}
+
+/// Moves the file at `old` to `new` on the same file system, replacing what
+/// is at `new`
+pub fn rename(old: &str, new: &str) -> Result<(), FsError> {
+    let (parent, name, fs) = walk_parent(old)?;
+    let (new_parent, new_name, new_fs) = walk_parent(new)?;
+    if !Arc::ptr_eq(&fs, &new_fs) {
+        return Err(FsError::CrossDevice);
+    }
+    parent.rename(name, &new_parent, new_name)
+}
+
+/// Mounts the initial ramdisk at /, the device file system at /dev, a tmpfs
+/// at /tmp and the FAT and ext2 volumes on the disks at /mnt/disk<n>, or
+/// /mnt/disk<n>p<m> for the ones in partitions
+pub fn init() {
+    match boot_info().module(b"initrd").map(|module| module.data()) {
+        Some(data) => match initrd::InitrdFs::new(data) {
+            Some(initrd) => mount("/", initrd).expect("/ is mounted twice"),
+            None => crate::info!("The initrd is no ustar or newc cpio archive"),
+        },
+        None => crate::info!("No initrd, nothing is mounted at /"),
+    }
+    mount("/dev", devfs::DevFs::new()).expect("/dev is mounted twice");
+    // like on Linux, /tmp may fill half of the memory the kernel heap can reach
+    let frames = FRAMES.stats();
+    let tmp_size = (frames.total - frames.high) / 2 * PAGE_SIZE;
+    mount("/tmp", tmpfs::TmpFs::new(tmp_size)).expect("/tmp is mounted twice");
+
+    for (i, disk) in (0..).map_while(|i| Some((i, DEVICES.disk(i)?))) {
+        if mount_volume(&format!("/mnt/disk{}", i), disk) {
+            continue;
+        }
+
+        for part in Partition::read_mbr(disk).unwrap_or_default() {
+            let part: &'static Partition = Box::leak(Box::new(part));
+            mount_volume(&format!("/mnt/disk{}p{}", i, part.number), part);
+        }
+    }
+}
+
+/// Mounts the file system on `disk` at `path`, false if there is none known
+fn mount_volume(path: &str, disk: &'static dyn BlockDevice) -> bool {
+    let fs: Arc<dyn FileSystem> = if let Some(fat) = fat::FatFs::mount(disk) {
+        fat
+    } else if let Some(ext2) = ext2::Ext2Fs::mount(disk) {
+        ext2
+    } else {
+        return false;
+    };
+    let _ = mount(path, fs);
+    true
+}
+
+/// Inode and the file system it is on
+type Resolved = (Arc<dyn Inode>, Arc<dyn FileSystem>);
+
+/// Walks from the root of the mount `path` is in down to it. A symbolic link
+/// restarts the walk with its target in place of the components up to it.
+/// Returns the file system the walk ended in too.
+fn walk(path: &str) -> Result<Resolved, FsError> {
+    let mut path = String::from(path);
+    for _ in 0..=MAX_SYMLINKS {
+        let components = components(&path)?;
+        let (fs, depth) = {
+            let mounts = MOUNTS.borrow_mut();
+            let mount = mounts
+                .iter()
+                .filter(|mount| {
+                    components.len() >= mount.path.len()
+                        && mount.path.iter().zip(&components).all(|(a, b)| a == b)
+                })
+                .max_by_key(|mount| mount.path.len())
+                .ok_or(FsError::NotFound)?;
+            (mount.fs.clone(), mount.path.len())
+        };
+
+        let mut inode = fs.root();
+        let mut target = None;
+        for (i, name) in components.iter().enumerate().skip(depth) {
+            inode = inode.lookup(name)?;
+            if inode.stat().kind == FileType::Symlink {
+                // relative targets start in the directory of the link
+                let link = inode.readlink()?;
+                let dir = if link.starts_with('/') {
+                    String::new()
+                } else {
+                    components[..i].join("/")
+                };
+                target = Some(format!(
+                    "/{}/{}/{}",
+                    dir,
+                    link,
+                    components[i + 1..].join("/")
+                ));
+                break;
+            }
+        }
+        match target {
+            Some(target) => path = target,
+            None => return Ok((inode, fs)),
+        }
+    }
+    Err(FsError::SymlinkLoop)
+}
+
+/// Directory, name in it and file system of the directory
+type ResolvedParent<'a> = (Arc<dyn Inode>, &'a str, Arc<dyn FileSystem>);
+
+/// Like `lookup_parent`, with the file system of the directory
+fn walk_parent(path: &str) -> Result<ResolvedParent<'_>, FsError> {
+    let mut components = components(path)?;
+    let name = components.pop().ok_or(FsError::Exists)?;
+    let (dir, fs) = walk(&format!("/{}", components.join("/")))?;
+    Ok((dir, name, fs))
+}
+
+/// Components of an absolute path, without `.` and with `..` applied
+fn components(path: &str) -> Result<Vec<&str>, FsError> {
+    let Some(path) = path.strip_prefix('/') else {
+        return Err(FsError::InvalidPath);
+    };
+
+    let mut components = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            // the parent of the root is the root
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    Ok(components)
+}
@@ -0,0 +1,447 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::cell::{Cell, RefCell};
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Stat};
+use crate::{
+    paging::{self, FRAMES, PAGE_SIZE, Page},
+    time::{self, NANOS_PER_SEC},
+};
+
+const ROOT_INO: u64 = 1;
+/// Longest name of a directory entry
+const NAME_MAX: usize = 255;
+
+/// Writable file system in memory. File contents live in page frames of the
+/// frame allocator, the directory tree on the heap, and all of it is lost at
+/// shutdown. Only regular files and directories can be created.
+pub struct TmpFs {
+    /// Page frames the file contents may take
+    limit: usize,
+    used: Cell<usize>,
+    /// Last inode number given out
+    ino: Cell<u64>,
+    /// Every file by inode number, for `link` and `rename` to find the one
+    /// they get as a `dyn Inode`
+    nodes: RefCell<BTreeMap<u64, Weak<Node>>>,
+    root: Arc<Node>,
+}
+
+struct Node {
+    fs: Weak<TmpFs>,
+    this: Weak<Node>,
+    ino: u64,
+    kind: FileType,
+    mtime: Cell<u64>,
+    /// Entries naming a file, 0 for a removed directory
+    nlink: Cell<u32>,
+    size: Cell<u64>,
+    /// Frames of a file by page index, holes read as zeroes. Bytes past the
+    /// size are always zero, so growing the file needs no clearing.
+    pages: RefCell<BTreeMap<u64, *mut Page>>,
+    entries: RefCell<BTreeMap<String, Arc<Node>>>,
+    /// Directory a directory is in, nothing for the root
+    parent: RefCell<Weak<Node>>,
+}
+
+impl TmpFs {
+    /// Empty file system for at most `size` bytes of file contents, rounded
+    /// down to pages
+    pub fn new(size: usize) -> Arc<Self> {
+        Arc::new_cyclic(|fs| {
+            let root = Node::new(fs.clone(), ROOT_INO, FileType::Directory);
+            Self {
+                limit: size / PAGE_SIZE,
+                used: Cell::new(0),
+                ino: Cell::new(ROOT_INO),
+                nodes: RefCell::new(BTreeMap::from([(ROOT_INO, Arc::downgrade(&root))])),
+                root,
+            }
+        })
+    }
+
+    fn new_node(&self, kind: FileType) -> Arc<Node> {
+        let ino = self.ino.get() + 1;
+        self.ino.set(ino);
+        let node = Node::new(self.root.fs.clone(), ino, kind);
+        self.nodes.borrow_mut().insert(ino, Arc::downgrade(&node));
+        node
+    }
+
+    /// The node of `inode`, which the VFS makes sure is one of this file system
+    fn node(&self, inode: &Arc<dyn Inode>) -> Result<Arc<Node>, FsError> {
+        self.nodes
+            .borrow()
+            .get(&inode.stat().ino)
+            .and_then(Weak::upgrade)
+            .ok_or(FsError::InvalidArgs)
+    }
+
+    /// Takes `count` more frames from the limit
+    fn reserve(&self, count: usize) -> Result<(), FsError> {
+        let used = self.used.get() + count;
+        if used > self.limit {
+            return Err(FsError::NoSpace);
+        }
+        self.used.set(used);
+        Ok(())
+    }
+
+    fn release(&self, count: usize) {
+        self.used.set(self.used.get() - count);
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl Node {
+    fn new(fs: Weak<TmpFs>, ino: u64, kind: FileType) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            fs,
+            this: this.clone(),
+            ino,
+            kind,
+            mtime: Cell::new(time::realtime() / NANOS_PER_SEC),
+            nlink: Cell::new(1),
+            size: Cell::new(0),
+            pages: RefCell::new(BTreeMap::new()),
+            entries: RefCell::new(BTreeMap::new()),
+            parent: RefCell::new(Weak::new()),
+        })
+    }
+
+    fn fs(&self) -> Arc<TmpFs> {
+        self.fs.upgrade().expect("tmpfs dropped while in use")
+    }
+
+    fn check_dir(&self) -> Result<(), FsError> {
+        match self.kind {
+            FileType::Directory => Ok(()),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    /// Like `check_dir`, for adding entries, which a removed directory can not get
+    fn check_new_entry(&self, name: &str) -> Result<(), FsError> {
+        self.check_dir()?;
+        if self.nlink.get() == 0 {
+            return Err(FsError::NotFound);
+        }
+        if name.is_empty()
+            || name.len() > NAME_MAX
+            || name.contains('/')
+            || name == "."
+            || name == ".."
+        {
+            return Err(FsError::InvalidPath);
+        }
+        Ok(())
+    }
+
+    fn check_file(&self) -> Result<(), FsError> {
+        match self.kind {
+            FileType::Regular => Ok(()),
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Result<Arc<Node>, FsError> {
+        self.check_dir()?;
+        self.entries
+            .borrow()
+            .get(name)
+            .cloned()
+            .ok_or(FsError::NotFound)
+    }
+
+    fn touch(&self) {
+        self.mtime.set(time::realtime() / NANOS_PER_SEC);
+    }
+
+    /// Removes the entry `name`, which is `node`, and drops the link of it
+    fn remove(&self, name: &str, node: &Node) {
+        self.entries.borrow_mut().remove(name);
+        node.nlink.set(node.nlink.get() - 1);
+        self.touch();
+    }
+
+    /// Frees the pages from byte `size` on and clears the rest of the page it is in
+    fn free_pages_from(&self, size: u64) {
+        let mut pages = self.pages.borrow_mut();
+        let freed = pages.split_off(&size.div_ceil(PAGE_SIZE as u64));
+        for &page in freed.values() {
+            free_page(page);
+        }
+        if let Some(fs) = self.fs.upgrade() {
+            fs.release(freed.len());
+        }
+
+        let offset = size as usize % PAGE_SIZE;
+        if offset != 0
+            && let Some(&page) = pages.get(&(size / PAGE_SIZE as u64))
+        {
+            unsafe { (&mut *page)[offset..].fill(0) };
+        }
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.free_pages_from(0);
+        if let Some(fs) = self.fs.upgrade() {
+            fs.nodes.borrow_mut().remove(&self.ino);
+        }
+    }
+}
+
+impl Inode for Node {
+    fn stat(&self) -> Stat {
+        // directories are linked from their parent, themselves and subdirectories
+        let nlink = match self.kind {
+            FileType::Directory if self.nlink.get() > 0 => {
+                let entries = self.entries.borrow();
+                2 + entries
+                    .values()
+                    .filter(|node| node.kind == FileType::Directory)
+                    .count() as u32
+            }
+            _ => self.nlink.get(),
+        };
+        Stat {
+            ino: self.ino,
+            kind: self.kind,
+            mode: match self.kind {
+                FileType::Directory => 0o755,
+                _ => 0o644,
+            },
+            nlink,
+            size: self.size.get(),
+            mtime: self.mtime.get(),
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        let size = self.size.get();
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buf
+            .len()
+            .min((size - offset).min(usize::MAX as u64) as usize);
+        let pages = self.pages.borrow();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let start = pos as usize % PAGE_SIZE;
+            let count = (PAGE_SIZE - start).min(len - done);
+            let chunk = &mut buf[done..done + count];
+            match pages.get(&(pos / PAGE_SIZE as u64)) {
+                Some(&page) => chunk.copy_from_slice(unsafe { &(&*page)[start..start + count] }),
+                None => chunk.fill(0),
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        self.check_file()?;
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(FsError::NoSpace)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // all frames first, a full file system leaves the file as it was
+        let fs = self.fs();
+        let mut pages = self.pages.borrow_mut();
+        let first = offset / PAGE_SIZE as u64;
+        let last = (end - 1) / PAGE_SIZE as u64;
+        let missing = (first..=last)
+            .filter(|index| !pages.contains_key(index))
+            .collect::<Vec<_>>();
+        fs.reserve(missing.len())?;
+        for (i, &index) in missing.iter().enumerate() {
+            match alloc_page() {
+                Ok(page) => {
+                    pages.insert(index, page);
+                }
+                Err(err) => {
+                    // the frames of the holes filled so far stay, they are zero
+                    fs.release(missing.len() - i);
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let start = pos as usize % PAGE_SIZE;
+            let count = (PAGE_SIZE - start).min(buf.len() - done);
+            let page = pages[&(pos / PAGE_SIZE as u64)];
+            unsafe { (&mut *page)[start..start + count].copy_from_slice(&buf[done..done + count]) };
+            done += count;
+        }
+
+        self.size.set(self.size.get().max(end));
+        self.touch();
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        self.check_file()?;
+        if size < self.size.get() {
+            self.free_pages_from(size);
+        }
+        self.size.set(size);
+        self.touch();
+        Ok(())
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.check_dir()?;
+        Ok(self
+            .entries
+            .borrow()
+            .iter()
+            .map(|(name, node)| DirEntry {
+                name: name.clone(),
+                ino: node.ino,
+                kind: node.kind,
+            })
+            .collect())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Ok(self.entry(name)?)
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        self.check_new_entry(name)?;
+        if !matches!(kind, FileType::Regular | FileType::Directory) {
+            return Err(FsError::Unsupported);
+        }
+        if self.entries.borrow().contains_key(name) {
+            return Err(FsError::Exists);
+        }
+
+        let node = self.fs().new_node(kind);
+        *node.parent.borrow_mut() = self.this.clone();
+        self.entries
+            .borrow_mut()
+            .insert(name.to_string(), node.clone());
+        self.touch();
+        Ok(node)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> Result<(), FsError> {
+        self.check_new_entry(name)?;
+        let node = self.fs().node(inode)?;
+        if node.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        // an unlinked file that is still open can not come back
+        if node.nlink.get() == 0 {
+            return Err(FsError::NotFound);
+        }
+        if self.entries.borrow().contains_key(name) {
+            return Err(FsError::Exists);
+        }
+
+        node.nlink.set(node.nlink.get() + 1);
+        self.entries.borrow_mut().insert(name.to_string(), node);
+        self.touch();
+        Ok(())
+    }
+
+    fn rename(&self, name: &str, dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), FsError> {
+        let node = self.entry(name)?;
+        let dir = self.fs().node(dir)?;
+        dir.check_new_entry(new_name)?;
+
+        let replaced = dir.entries.borrow().get(new_name).cloned();
+        if let Some(replaced) = &replaced {
+            if Arc::ptr_eq(replaced, &node) {
+                return Ok(());
+            }
+            match (node.kind, replaced.kind) {
+                (FileType::Directory, FileType::Directory) => {
+                    if !replaced.entries.borrow().is_empty() {
+                        return Err(FsError::NotEmpty);
+                    }
+                }
+                (FileType::Directory, _) => return Err(FsError::NotADirectory),
+                (_, FileType::Directory) => return Err(FsError::IsADirectory),
+                _ => {}
+            }
+        }
+
+        if node.kind == FileType::Directory {
+            // a directory can not move into itself or below
+            let mut ancestor = Some(dir.clone());
+            while let Some(current) = ancestor {
+                if Arc::ptr_eq(&current, &node) {
+                    return Err(FsError::InvalidArgs);
+                }
+                ancestor = current.parent.borrow().upgrade();
+            }
+            *node.parent.borrow_mut() = dir.this.clone();
+        }
+
+        if let Some(replaced) = &replaced {
+            dir.remove(new_name, replaced);
+        }
+        self.entries.borrow_mut().remove(name);
+        dir.entries.borrow_mut().insert(new_name.to_string(), node);
+        self.touch();
+        dir.touch();
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let node = self.entry(name)?;
+        if node.kind == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        self.remove(name, &node);
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), FsError> {
+        let node = self.entry(name)?;
+        node.check_dir()?;
+        if !node.entries.borrow().is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+        self.remove(name, &node);
+        Ok(())
+    }
+}
+
+/// Zeroed page frame, the file system has reserved it already
+fn alloc_page() -> Result<*mut Page, FsError> {
+    let frame = FRAMES.alloc().map_err(|_| FsError::NoSpace)?;
+    let page = paging::phys_to_virt::<Page>(frame as usize);
+    unsafe { page.write_bytes(0, 1) };
+    Ok(page)
+}
+
+fn free_page(page: *mut Page) {
+    FRAMES.free(paging::virt_to_phys(page) as _);
+}
@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use core::arch::asm;
 use core::cell::Cell;
 use core::mem;
 
@@ -8,12 +9,6 @@ pub const USER_CS: u16 = 0x18 | 0b11;
 pub const USER_DS: u16 = 0x20 | 0b11;
 
 pub static GDT: Gdt = Gdt::new();
-#[unsafe(link_section = ".boot")]
-#[unsafe(no_mangle)]
-pub static GDT_DESC: GdtDescriptor = GdtDescriptor {
-    size: (mem::size_of_val(&GDT) - 1) as u16,
-    offset: &raw const GDT,
-};
 
 bitflags! {
     pub struct SDFlags0 : u8 {
@@ -173,6 +168,35 @@ impl Gdt {
             tss: Cell::new(SegmentDescriptor::tss()),
         }
     }
+
+    pub fn load(&'static self) {
+        let desc = GdtDescriptor {
+            size: (mem::size_of::<Gdt>() - 1) as u16,
+            offset: self,
+        };
+
+        unsafe {
+            asm!(
+                "lgdt [{desc}]",
+                // reload cs with a far return
+                "push {cs}",
+                "lea {tmp}, [2f]",
+                "push {tmp}",
+                "retf",
+                "2:",
+                "mov {tmp:x}, {ds}",
+                "mov ds, {tmp:x}",
+                "mov es, {tmp:x}",
+                "mov fs, {tmp:x}",
+                "mov gs, {tmp:x}",
+                "mov ss, {tmp:x}",
+                desc = in(reg) &desc,
+                cs = const KERNEL_CS,
+                ds = const KERNEL_DS,
+                tmp = out(reg) _,
+            )
+        }
+    }
 }
 
 unsafe impl Sync for Gdt {}
@@ -1,46 +1,204 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
-    sync::atomic::{AtomicUsize, Ordering},
+    mem,
+    ops::Range,
+    ptr,
 };
 
-const ARENA_START: usize = 0x100000;
-const ARENA_END: usize = 0x400000;
+use utils::nullsync;
 
-pub struct LinearAllocator {
-    cur: AtomicUsize,
+use crate::{
+    critical_section,
+    paging::{self, FRAMES, PAGE_SIZE},
+};
+
+/// Heap memory at startup, before the frame allocator exists
+const INITIAL_SIZE: usize = 0x80000;
+const GROW_FRAMES: usize = 16;
+
+unsafe extern "C" {
+    static k_end: u8;
 }
 
-unsafe impl GlobalAlloc for LinearAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        loop {
-            let cur = self.cur.load(Ordering::Acquire);
-            let aligned = (cur + layout.align() - 1) & !(layout.align() - 1);
-            let new = aligned + layout.size();
-
-            if new > ARENA_END {
-                panic!(
-                    "OOM: the arena is not enough to allocate the layout\n\
-                    {:?}\n\
-                    arena_current: {:x?}\n\
-                    arena_end: {:x?}",
-                    layout, cur, ARENA_END
-                );
+pub fn arena() -> Range<usize> {
+    let start = &raw const k_end as usize;
+    start..(start + INITIAL_SIZE).next_multiple_of(PAGE_SIZE)
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+const MIN_BLOCK: usize = mem::size_of::<FreeBlock>();
+
+/// First fit allocator over an address ordered list of free blocks.
+/// Neighbouring blocks are merged on free, frames are requested when
+/// no block fits.
+pub struct Heap {
+    state: nullsync::RefCell<HeapState>,
+}
+
+struct HeapState {
+    head: *mut FreeBlock,
+    initialized: bool,
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            state: nullsync::RefCell::new(HeapState {
+                head: ptr::null_mut(),
+                initialized: false,
+            }),
+        }
+    }
+
+    fn grow(&self, size: usize, align: usize) -> bool {
+        let frames = (size + align + MIN_BLOCK)
+            .div_ceil(PAGE_SIZE)
+            .max(GROW_FRAMES);
+
+        // the frame allocator may allocate itself, so the heap must not be borrowed here
+        match FRAMES.alloc_contiguous(frames, PAGE_SIZE) {
+            Ok(start) => {
+                let start = paging::phys_to_virt::<u8>(start as usize) as usize;
+                let mut state = self.state.borrow_mut();
+                unsafe { state.insert(start, frames * PAGE_SIZE) };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl HeapState {
+    fn init(&mut self) {
+        if !self.initialized {
+            let arena = arena();
+            unsafe { self.insert(arena.start, arena.len()) };
+            self.initialized = true;
+        }
+    }
+
+    fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut cur = self.head;
+
+        unsafe {
+            while !cur.is_null() {
+                let start = cur as usize;
+                let end = start + (*cur).size;
+
+                let mut aligned = start.next_multiple_of(align);
+                if aligned != start && aligned - start < MIN_BLOCK {
+                    aligned = (start + MIN_BLOCK).next_multiple_of(align);
+                }
+
+                let rest = end.checked_sub(aligned + size);
+                if let Some(rest) = rest.filter(|&rest| rest == 0 || rest >= MIN_BLOCK) {
+                    let next = (*cur).next;
+                    let after = if rest == 0 {
+                        next
+                    } else {
+                        let block = (aligned + size) as *mut FreeBlock;
+                        block.write(FreeBlock { size: rest, next });
+                        block
+                    };
+
+                    if aligned != start {
+                        (*cur).size = aligned - start;
+                        (*cur).next = after;
+                    } else if prev.is_null() {
+                        self.head = after;
+                    } else {
+                        (*prev).next = after;
+                    }
+                    return aligned as _;
+                }
+
+                prev = cur;
+                cur = (*cur).next;
+            }
+        }
+
+        ptr::null_mut()
+    }
+
+    /// Puts `start..start + size` back into the list, merging it with the neighbours
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head;
+
+        unsafe {
+            while !next.is_null() && (next as usize) < start {
+                prev = next;
+                next = (*next).next;
+            }
+
+            debug_assert!(
+                next.is_null() || start + size <= next as usize,
+                "Heap double free"
+            );
+            debug_assert!(
+                prev.is_null() || prev as usize + (*prev).size <= start,
+                "Heap double free"
+            );
+
+            let block = start as *mut FreeBlock;
+            block.write(FreeBlock { size, next });
+
+            if !next.is_null() && start + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
             }
 
-            if self
-                .cur
-                .compare_exchange_weak(cur, new, Ordering::AcqRel, Ordering::Acquire)
-                .is_ok()
-            {
-                return aligned as _;
+            if prev.is_null() {
+                self.head = block;
+            } else if prev as usize + (*prev).size == start {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            } else {
+                (*prev).next = block;
             }
         }
     }
+}
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let align = layout.align().max(mem::align_of::<FreeBlock>());
+    let size = layout
+        .size()
+        .max(MIN_BLOCK)
+        .next_multiple_of(mem::align_of::<FreeBlock>());
+    (size, align)
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+
+        critical_section::wrap(|| {
+            loop {
+                let res = {
+                    let mut state = self.state.borrow_mut();
+                    state.init();
+                    state.alloc(size, align)
+                };
+
+                if !res.is_null() || !self.grow(size, align) {
+                    return res;
+                }
+            }
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(layout);
+
+        critical_section::wrap(|| unsafe { self.state.borrow_mut().insert(ptr as usize, size) })
+    }
 }
 
 #[global_allocator]
-static GLOBAL: LinearAllocator = LinearAllocator {
-    cur: AtomicUsize::new(ARENA_START),
-};
+static GLOBAL: Heap = Heap::new();
@@ -191,8 +191,9 @@ extern "C" fn global_handler(ctx: *mut InterruptContext) {
     }
 
     let mut ctx = unsafe { &mut *ctx };
-    let us_bit = ctx.errcode & (1 << 2) != 0;
-    if us_bit {
+    // exceptions caused by user space, IRQs and syscalls go to their handlers
+    let from_user = ctx.cs & 0b11 != 0;
+    if ctx.vector < 0x20 && from_user {
         return user_global_handler(ctx);
     }
 
@@ -3,42 +3,53 @@
 
 extern crate alloc;
 
+mod boot_info;
 mod critical_section;
 mod device_manager;
 mod drivers;
+mod elf;
 mod entry;
+mod fs;
 mod gdt;
 mod global_alloc;
 mod interrupts;
+mod multiboot;
 mod paging;
 mod panic;
 mod process;
 mod syscalls;
+mod time;
 mod tss;
 mod x86_utils;
 
+use alloc::boxed::Box;
 use core::{
     cell::{LazyCell, RefCell},
-    mem,
+    fmt, mem,
 };
 use device_manager::DEVICES;
-use utils::nullsync;
 use utils::textbuffer::TextBuffer;
 use utils::textbuffer::TextBufferWritter;
+use utils::{io::Write, nullsync};
 
 use crate::{
+    boot_info::boot_info,
     gdt::GDT,
     interrupts::Idt,
-    process::{CUR_PROCCESS, PROCESSES},
+    paging::FRAMES,
+    process::{
+        Tty,
+        scheduler::{Mlfq, RoundRobin},
+    },
+    time::TICK_HZ,
     tss::TSS,
-    x86_utils::{cli, sti, tsc_sleep},
+    x86_utils::{cli, sti},
 };
 
 macro_rules! print {
-    ($($arg:tt)*) => {{
-        use utils::io::Write;
-        write!($crate::TBW.borrow_mut(), $($arg)*).unwrap()
-    }};
+    ($($arg:tt)*) => {
+        $crate::print_args(format_args!($($arg)*))
+    };
 }
 
 macro_rules! println {
@@ -57,44 +68,51 @@ macro_rules! info {
     }};
 }
 
-macro_rules! warning {
-    ($($arg:tt)*) => {{
-        let mut tbw = $crate::TBW.borrow_mut();
-        $crate::print!("[");
-        $crate::TBW.borrow_mut().set_next_fg(0x00ffff00);
-        $crate::print!("WARNING");
-        $crate::TBW.borrow_mut().set_next_fg(0x00ffffff);
-        $crate::println!("] {}", format_args!($($arg)*));
-    }};
-}
 pub(crate) use info;
 pub(crate) use print;
 pub(crate) use println;
 
-unsafe extern "C" {
-    static framebuffer_addr: *mut u32;
-    static framebuffer_width: u16;
-    static framebuffer_height: u16;
-}
-
 static TBW: nullsync::Marker<LazyCell<RefCell<TextBufferWritter>>> =
     nullsync::Marker::new(LazyCell::new(|| {
+        let fb = &boot_info().framebuffer;
         RefCell::new(TextBufferWritter::new(TextBuffer::new(
             utils::framebuffer::Framebuffer {
-                addr: unsafe { framebuffer_addr },
-                width: unsafe { framebuffer_width as usize },
-                height: unsafe { framebuffer_height as usize },
+                addr: paging::map_framebuffer(),
+                width: fb.width,
+                height: fb.height,
             },
         )))
     }));
 
+/// Writes to the screen, mirrored to the serial log
+pub fn print_args(args: fmt::Arguments) {
+    TBW.borrow_mut().write_fmt(args).unwrap();
+    (&DEVICES.com1).write_fmt(args).unwrap();
+}
+
+/// Boot module, place on the screen and arguments of a process started at boot
+type InitialProcess = (&'static [u8], usize, usize, &'static [&'static [u8]]);
+
 pub fn kmain() {
+    DEVICES.init_serial();
+    GDT.load();
+    paging::init();
     TBW.borrow_mut().clear();
 
     let mut idt = Idt::new();
     idt.load();
     info!("IDT loaded");
 
+    info!(
+        "{} MiB of usable memory",
+        boot_info().free_memory(0).total() / (1024 * 1024)
+    );
+    let frames = FRAMES.stats();
+    info!(
+        "Frames: {} total, {} used, {} free, {} above the direct map",
+        frames.total, frames.used, frames.free, frames.high
+    );
+
     GDT.tss.update(|mut tss_desc| {
         tss_desc.set_base(&raw const TSS as u32);
         tss_desc.set_limit((mem::size_of_val(&TSS) - 1) as u32);
@@ -105,6 +123,10 @@ pub fn kmain() {
     info!("TSS loaded");
 
     DEVICES.init_devices();
+    time::init();
+    info!("TSC runs at {} MHz", time::tsc_hz() / 1_000_000);
+
+    fs::init();
 
     interrupts::register_handler(0x80, syscalls::generic_handler);
     idt.mark_syscall(0x80);
@@ -116,30 +138,46 @@ pub fn kmain() {
     println!("Press any key...");
 
     while DEVICES.ps2keyboard.read() == 0 {
-        tsc_sleep(1000);
+        time::delay(1_000_000);
     }
 
     TBW.borrow_mut().clear();
 
-    unsafe {
-        PROCESSES[0].init(&[b"binary\0", b"--flag=true\0", b"src.c\0", b"a.out\0", b"\0"]);
-        PROCESSES[1].init(&[]);
-        PROCESSES[2].init(&[]);
-        PROCESSES[3].init(&[]);
-
-        interrupts::register_handler(0x20, |ctx| {
-            let mut next = (CUR_PROCCESS + 1) % 4;
-
-            while !PROCESSES[next].alive {
-                next = (next + 1) % 4;
-            }
-
-            PROCESSES[CUR_PROCCESS].ctx = ctx.clone();
-            CUR_PROCCESS = next;
-            PROCESSES[CUR_PROCCESS].jump();
-        });
-        cli();
-        DEVICES.pic.enable_device(0);
-        PROCESSES[0].jump();
-    };
+    let initial: [InitialProcess; 4] = [
+        (
+            b"userspace1",
+            0,
+            0,
+            &[b"binary\0", b"--flag=true\0", b"src.c\0", b"a.out\0", b"\0"],
+        ),
+        (b"userspace2", 1, 0, &[]),
+        (b"userspace3", 0, 1, &[]),
+        (b"userspace4", 1, 1, &[]),
+    ];
+    if cfg!(feature = "shell") {
+        let tty = Tty::Screen(process::template_writer(0, 0, 1, 1));
+        process::spawn(b"/bin/shell", &[], tty, None).expect("Failed to start /bin/shell");
+    } else {
+        for (name, x, y, args) in initial {
+            let tty = Tty::Screen(process::template_writer(x, y, 2, 2));
+            process::spawn(name, args, tty, None).expect("Failed to start the initial processes");
+        }
+    }
+
+    if cfg!(feature = "round-robin") {
+        // 10 ms slices
+        process::scheduler::set_scheduler(Box::new(RoundRobin::new(TICK_HZ / 100)));
+    } else {
+        // 5 ms slices on top, up to 80 ms at the bottom, everything goes back up every second
+        process::scheduler::set_scheduler(Box::new(Mlfq::new(
+            [50, 100, 150, 200, 300, 400, 600, 800],
+            TICK_HZ,
+        )));
+    }
+    info!("Scheduler: {}", process::scheduler().name());
+
+    interrupts::register_handler(0x20, time::timer_interrupt);
+    cli();
+    DEVICES.pic.enable_device(0);
+    process::start();
 }
@@ -0,0 +1,224 @@
+use core::{ffi::CStr, mem};
+
+use crate::{
+    boot_info::{BootInfo, FramebufferInfo, MemoryKind, MemoryRegion, Module},
+    paging::phys_to_virt,
+};
+
+pub const MULTIBOOT_MAGIC: u32 = 0x2badb002;
+pub const MULTIBOOT2_MAGIC: u32 = 0x36d76289;
+
+const INFO_MEMORY: u32 = 1 << 0;
+const INFO_MODULES: u32 = 1 << 3;
+const INFO_MEMORY_MAP: u32 = 1 << 6;
+const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+const TAG_END: u32 = 0;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+
+const FRAMEBUFFER_RGB: u8 = 1;
+
+#[repr(C, packed)]
+struct Info {
+    flags: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+    drives_length: u32,
+    drives_addr: u32,
+    config_table: u32,
+    boot_loader_name: u32,
+    apm_table: u32,
+    vbe_control_info: u32,
+    vbe_mode_info: u32,
+    vbe_mode: u16,
+    vbe_interface_seg: u16,
+    vbe_interface_off: u16,
+    vbe_interface_len: u16,
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+}
+
+#[repr(C, packed)]
+struct ModuleEntry {
+    start: u32,
+    end: u32,
+    string: u32,
+    _reserved: u32,
+}
+
+#[repr(C, packed)]
+struct MemoryMapEntry {
+    size: u32,
+    base: u64,
+    len: u64,
+    kind: u32,
+}
+
+#[repr(C, packed)]
+struct Tag {
+    kind: u32,
+    size: u32,
+}
+
+#[repr(C, packed)]
+struct ModuleTag {
+    tag: Tag,
+    start: u32,
+    end: u32,
+}
+
+#[repr(C, packed)]
+struct MemoryMapTag {
+    tag: Tag,
+    entry_size: u32,
+    entry_version: u32,
+}
+
+#[repr(C, packed)]
+struct MemoryMapTagEntry {
+    base: u64,
+    len: u64,
+    kind: u32,
+    _reserved: u32,
+}
+
+#[repr(C, packed)]
+struct FramebufferTag {
+    tag: Tag,
+    addr: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bpp: u8,
+    kind: u8,
+}
+
+/// `info` is the virtual address of the boot information, the addresses inside are physical
+pub fn parse(magic: u32, info: *const u8, boot_info: &mut BootInfo) {
+    match magic {
+        MULTIBOOT_MAGIC => unsafe { parse_v1(&*(info as *const Info), boot_info) },
+        MULTIBOOT2_MAGIC => unsafe { parse_v2(info, boot_info) },
+        _ => panic!("Unknown bootloader magic: {:#x}", magic),
+    }
+}
+
+unsafe fn parse_v1(info: &Info, boot_info: &mut BootInfo) {
+    let flags = info.flags;
+
+    if flags & INFO_MODULES != 0 {
+        let modules = phys_to_virt::<ModuleEntry>(info.mods_addr as usize);
+        for i in 0..info.mods_count as usize {
+            let module = unsafe { &*modules.add(i) };
+            let name = match module.string {
+                0 => &[],
+                string => unsafe { module_name(phys_to_virt(string as usize)) },
+            };
+            boot_info.push_module(Module::new(module.start as _, module.end as _, name));
+        }
+    }
+
+    if flags & INFO_MEMORY_MAP != 0 {
+        let mut entry = phys_to_virt::<u8>(info.mmap_addr as usize) as usize;
+        let end = entry + info.mmap_length as usize;
+
+        while entry < end {
+            let region = unsafe { &*(entry as *const MemoryMapEntry) };
+            boot_info.push_memory_region(MemoryRegion {
+                start: region.base,
+                len: region.len,
+                kind: MemoryKind::from_e820(region.kind),
+            });
+            entry += region.size as usize + mem::size_of::<u32>();
+        }
+    } else if flags & INFO_MEMORY != 0 {
+        // no map, only the amount of memory above 1 MiB
+        boot_info.push_memory_region(MemoryRegion {
+            start: 0x100000,
+            len: info.mem_upper as u64 * 1024,
+            kind: MemoryKind::Usable,
+        });
+    }
+
+    if flags & INFO_FRAMEBUFFER != 0 && info.framebuffer_type == FRAMEBUFFER_RGB {
+        boot_info.framebuffer = FramebufferInfo {
+            addr: info.framebuffer_addr as _,
+            pitch: info.framebuffer_pitch as _,
+            width: info.framebuffer_width as _,
+            height: info.framebuffer_height as _,
+            bpp: info.framebuffer_bpp,
+        };
+    }
+}
+
+unsafe fn parse_v2(info: *const u8, boot_info: &mut BootInfo) {
+    // skip total_size and reserved fields
+    let mut tag = unsafe { info.add(8) };
+
+    loop {
+        let header = unsafe { &*(tag as *const Tag) };
+
+        match header.kind {
+            TAG_END => break,
+            TAG_MODULE => {
+                let module = unsafe { &*(tag as *const ModuleTag) };
+                let name = unsafe { module_name(tag.add(mem::size_of::<ModuleTag>())) };
+                boot_info.push_module(Module::new(module.start as _, module.end as _, name));
+            }
+            TAG_MEMORY_MAP => {
+                let map = unsafe { &*(tag as *const MemoryMapTag) };
+                let mut entry = mem::size_of::<MemoryMapTag>();
+
+                while entry < map.tag.size as usize {
+                    let region = unsafe { &*(tag.add(entry) as *const MemoryMapTagEntry) };
+                    boot_info.push_memory_region(MemoryRegion {
+                        start: region.base,
+                        len: region.len,
+                        kind: MemoryKind::from_e820(region.kind),
+                    });
+                    entry += map.entry_size as usize;
+                }
+            }
+            TAG_FRAMEBUFFER => {
+                let fb = unsafe { &*(tag as *const FramebufferTag) };
+                if fb.kind == FRAMEBUFFER_RGB {
+                    boot_info.framebuffer = FramebufferInfo {
+                        addr: fb.addr as _,
+                        pitch: fb.pitch as _,
+                        width: fb.width as _,
+                        height: fb.height as _,
+                        bpp: fb.bpp,
+                    };
+                }
+            }
+            _ => (),
+        }
+
+        tag = unsafe { tag.add((header.size as usize + 7) & !7) };
+    }
+}
+
+/// Module command line is "path args...", the module is named after the file
+/// without extension: "/boot/userspace1.bin" becomes "userspace1"
+unsafe fn module_name<'a>(cmdline: *const u8) -> &'a [u8] {
+    if cmdline.is_null() {
+        return &[];
+    }
+
+    let cmdline = unsafe { CStr::from_ptr(cmdline as _) }.to_bytes();
+    let path = cmdline.split(|&c| c == b' ').next().unwrap_or(&[]);
+    let file = path.rsplit(|&c| c == b'/').next().unwrap_or(&[]);
+    file.split(|&c| c == b'.').next().unwrap_or(&[])
+}
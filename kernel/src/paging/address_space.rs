@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+use core::arch::asm;
+
+use super::{
+    FRAMES, KERNEL_BASE, KMap, OutOfMemory, PAGE_SIZE, PageDirectory, PageDirectoryEntry,
+    PageFlags, PageTable, PageTableEntry, alloc_page, free_page, invlpg, kernel_page_directory,
+    kmap, load_page_directory, new_page_directory, page_table, virt_to_phys,
+};
+
+const USER_PDES: usize = KERNEL_BASE >> 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Data,
+    Stack,
+    Args,
+}
+
+/// Range of user addresses with a purpose, the pages inside may be mapped lazily
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub kind: RegionKind,
+}
+
+/// User half of a page directory, the kernel half is shared with every other
+/// address space. Owned frames and all page tables are freed on drop.
+pub struct AddressSpace {
+    pd: *mut PageDirectory,
+    regions: Vec<Region>,
+}
+
+impl Region {
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            pd: new_page_directory()?,
+            regions: Vec::new(),
+        })
+    }
+
+    pub fn activate(&self) {
+        load_page_directory(self.pd);
+    }
+
+    /// Maps `virt` to the physical `frame`, replacing the previous mapping
+    pub fn map(&mut self, virt: usize, frame: usize, flags: PageFlags) -> Result<(), OutOfMemory> {
+        debug_assert!(virt < KERNEL_BASE && virt.is_multiple_of(PAGE_SIZE));
+        let pt = self.table_or_create(virt)?;
+        let pte = &mut pt[(virt >> 12) & 0x3ff];
+
+        if pte.present() && pte.flags().contains(PageFlags::OWNED) {
+            FRAMES.free(pte.page_addr() as _);
+        }
+        *pte = PageTableEntry::with_flags(frame, flags | PageFlags::PRESENT);
+        invlpg(virt);
+        Ok(())
+    }
+
+    /// Maps a new zeroed frame owned by the address space, returns the kernel view of it
+    pub fn map_zeroed(&mut self, virt: usize, flags: PageFlags) -> Result<KMap, OutOfMemory> {
+        let frame = FRAMES.alloc_user()? as usize;
+        let page = kmap(frame);
+        unsafe { page.as_ptr().write_bytes(0, 1) };
+
+        self.map(virt, frame, flags | PageFlags::OWNED)
+            .inspect_err(|_| FRAMES.free(frame as _))?;
+        Ok(page)
+    }
+
+    pub fn entry(&self, virt: usize) -> Option<PageTableEntry> {
+        let pte = self.table(virt)?[(virt >> 12) & 0x3ff];
+        pte.present().then_some(pte)
+    }
+
+    /// Replaces the access flags of a mapped page, returns false if it is not mapped
+    pub fn protect(&mut self, virt: usize, flags: PageFlags) -> bool {
+        let Some(pt) = self.table(virt) else {
+            return false;
+        };
+        let pte = &mut pt[(virt >> 12) & 0x3ff];
+        if !pte.present() {
+            return false;
+        }
+
+        let owned = pte.flags() & PageFlags::OWNED;
+        *pte = PageTableEntry::with_flags(
+            pte.page_addr() as usize,
+            flags | owned | PageFlags::PRESENT,
+        );
+        invlpg(virt);
+        true
+    }
+
+    pub fn add_region(&mut self, region: Region) {
+        debug_assert!(
+            self.regions
+                .iter()
+                .all(|r| region.end <= r.start || r.end <= region.start)
+        );
+        self.regions.push(region);
+    }
+
+    pub fn region(&self, addr: usize) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(addr))
+    }
+
+    /// Copy of the user half. Owned writable pages become copy on write in
+    /// both address spaces, read only ones are shared as they are.
+    pub fn fork(&mut self) -> Result<AddressSpace, OutOfMemory> {
+        let mut child = AddressSpace::new()?;
+        child.regions = self.regions.clone();
+
+        for i in 0..USER_PDES {
+            let pde = unsafe { &(*self.pd)[i] };
+            if !pde.present() {
+                continue;
+            }
+
+            let pt = page_table(pde);
+            for (j, pte) in pt.iter_mut().enumerate().filter(|(_, pte)| pte.present()) {
+                let mut flags = pte.flags();
+                let frame = pte.page_addr() as usize;
+
+                if flags.contains(PageFlags::OWNED | PageFlags::WRITABLE) {
+                    flags = (flags - PageFlags::WRITABLE) | PageFlags::COW;
+                    *pte = PageTableEntry::with_flags(frame, flags);
+                }
+
+                child.map((i << 22) | (j << 12), frame, flags)?;
+                // the child owns a reference to the frame now
+                if flags.contains(PageFlags::OWNED) {
+                    FRAMES.share(frame as _);
+                }
+            }
+        }
+
+        // the writable pages of this address space just became read only
+        if is_active(self.pd) {
+            load_page_directory(self.pd);
+        }
+        Ok(child)
+    }
+
+    /// Gives the page of `virt` a private writable frame if it is copy on write,
+    /// returns false if it is not
+    pub fn copy_on_write(&mut self, virt: usize) -> Result<bool, OutOfMemory> {
+        let page = virt & !(PAGE_SIZE - 1);
+        let Some(pte) = self.entry(page) else {
+            return Ok(false);
+        };
+        if !pte.flags().contains(PageFlags::COW) {
+            return Ok(false);
+        }
+
+        let flags = (pte.flags() - PageFlags::COW - PageFlags::OWNED - PageFlags::PRESENT)
+            | PageFlags::WRITABLE;
+        let frame = pte.page_addr() as usize;
+
+        if FRAMES.is_shared(frame as _) {
+            let copy = FRAMES.alloc_user()? as usize;
+            unsafe {
+                kmap(copy)
+                    .as_ptr()
+                    .copy_from_nonoverlapping(kmap(frame).as_ptr(), 1)
+            };
+            // drops the reference to the shared frame
+            self.map(page, copy, flags | PageFlags::OWNED)
+                .inspect_err(|_| FRAMES.free(copy as _))?;
+        } else {
+            // every other reference is gone, the frame is ours alone
+            self.protect(page, flags);
+        }
+        Ok(true)
+    }
+
+    /// Unmaps the whole user half, freeing owned frames and page tables
+    pub fn clear(&mut self) {
+        for i in 0..USER_PDES {
+            let pde = unsafe { &mut (*self.pd)[i] };
+            if !pde.present() {
+                continue;
+            }
+
+            let pt = page_table(pde);
+            for pte in pt.iter().filter(|pte| pte.present()) {
+                if pte.flags().contains(PageFlags::OWNED) {
+                    FRAMES.free(pte.page_addr() as _);
+                }
+            }
+            free_page(&raw mut *pt);
+            *pde = PageDirectoryEntry::empty();
+        }
+
+        self.regions.clear();
+        // the page tables are gone, so are all the translations of the user half
+        if is_active(self.pd) {
+            load_page_directory(self.pd);
+        }
+    }
+
+    fn table(&self, virt: usize) -> Option<&'static mut PageTable> {
+        let pde = unsafe { &(*self.pd)[virt >> 22] };
+        pde.present().then(|| page_table(pde))
+    }
+
+    fn table_or_create(&mut self, virt: usize) -> Result<&'static mut PageTable, OutOfMemory> {
+        if let Some(pt) = self.table(virt) {
+            return Ok(pt);
+        }
+
+        let pt = alloc_page::<PageTable>()?;
+        unsafe {
+            pt.write_bytes(0, 1);
+            (*self.pd)[virt >> 22] =
+                PageDirectoryEntry::new_4kb(virt_to_phys(pt) as _, true, true, true);
+            Ok(&mut *pt)
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        self.clear();
+        if is_active(self.pd) {
+            load_page_directory(kernel_page_directory());
+        }
+        free_page(self.pd);
+    }
+}
+
+fn is_active(pd: *mut PageDirectory) -> bool {
+    let cr3: usize;
+    unsafe { asm!("mov {}, cr3", out(reg) cr3) };
+    cr3 == virt_to_phys(pd)
+}
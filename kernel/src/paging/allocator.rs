@@ -1,75 +1,255 @@
-use core::ptr;
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::ops::Range;
 use utils::nullsync;
 
+use crate::{
+    boot_info::{MemoryRanges, boot_info},
+    global_alloc,
+};
 
-const ARENA_START: usize = 0x400000;
-unsafe extern "C" {
-    static RAM_SIZE: usize;
-}
-
-type Pool4K = PoolAllocator<4096>;
+use super::{DIRECT_MAP_SIZE, PAGE_SIZE, virt_to_phys};
 
-pub static POOL4K: nullsync::LazyCell<Pool4K> = nullsync::LazyCell::new(|| {
-    Pool4K::new(
-        ptr::null_mut(),
-        ARENA_START as _,
-        (unsafe { RAM_SIZE }) as _,
-    )
+/// All free memory, frames above the direct map only go to user pages
+pub static FRAMES: nullsync::LazyCell<FrameAllocator> = nullsync::LazyCell::new(|| {
+    let heap_end = virt_to_phys(global_alloc::arena().end as *const u8);
+    FrameAllocator::new(&boot_info().free_memory(heap_end))
 });
 
-pub struct PoolAllocator<const N: usize> {
-    state: nullsync::RefCell<PoolAllocatorState>,
+#[derive(Debug, Clone, Copy)]
+pub struct OutOfMemory;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+    /// Frames above the direct map, in `total`
+    pub high: usize,
 }
 
-struct PoolAllocatorState {
-    pub freed: *mut *mut u8,
-    pub current: *mut u8,
-    pub end: *mut u8,
+/// Physical frame allocator, one bit per frame between the lowest and the
+/// highest free address. A set bit is a used (or never usable) frame.
+pub struct FrameAllocator {
+    state: nullsync::RefCell<FrameAllocatorState>,
+    /// References beyond the first one to frames mapped more than once.
+    /// Separate from the state, inserting may grow the heap.
+    shared: nullsync::RefCell<BTreeMap<usize, usize>>,
 }
 
-impl<const N: usize> PoolAllocator<N> {
-    pub const fn new(freed: *mut *mut u8, current: *mut u8, end: *mut u8) -> Self {
+struct FrameAllocatorState {
+    bitmap: Vec<u32>,
+    /// Multiple of 32, the direct map ends at a word of the bitmap
+    first_frame: usize,
+    frames: usize,
+    /// Frames below this index are in the direct map
+    direct_frames: usize,
+    total: usize,
+    high: usize,
+    free: usize,
+    next: usize,
+    next_high: usize,
+}
+
+impl FrameAllocator {
+    pub fn new(arenas: &MemoryRanges) -> Self {
+        let first_frame = arenas
+            .iter()
+            .map(|r| r.start / PAGE_SIZE)
+            .min()
+            .unwrap_or(0)
+            / 32
+            * 32;
+        let last_frame = arenas.iter().map(|r| r.end / PAGE_SIZE).max().unwrap_or(0);
+        let frames = last_frame.saturating_sub(first_frame);
+        let direct_frames = (DIRECT_MAP_SIZE / PAGE_SIZE)
+            .saturating_sub(first_frame)
+            .min(frames);
+
+        let mut state = FrameAllocatorState {
+            bitmap: vec![u32::MAX; frames.div_ceil(32)],
+            first_frame,
+            frames,
+            direct_frames,
+            total: 0,
+            high: 0,
+            free: 0,
+            next: 0,
+            next_high: direct_frames,
+        };
+
+        for arena in arenas.iter() {
+            for frame in arena.start / PAGE_SIZE..arena.end / PAGE_SIZE {
+                state.set_used(frame - first_frame, false);
+                if frame - first_frame >= direct_frames {
+                    state.high += 1;
+                }
+            }
+            state.total += arena.len() / PAGE_SIZE;
+        }
+        state.free = state.total;
+
         Self {
-            state: nullsync::RefCell::new(PoolAllocatorState {
-                freed,
-                current,
-                end,
-            }),
+            state: nullsync::RefCell::new(state),
+            shared: nullsync::RefCell::new(BTreeMap::new()),
         }
     }
 
-    pub fn alloc(&self) -> *mut u8 {
+    /// A frame in the direct map
+    pub fn alloc(&self) -> Result<*mut u8, OutOfMemory> {
+        let mut state = self.state.borrow_mut();
+        let index = state
+            .find_free(0..state.direct_frames, state.next)
+            .ok_or(OutOfMemory)?;
+
+        state.set_used(index, true);
+        state.free -= 1;
+        state.next = index + 1;
+        Ok(state.address(index))
+    }
+
+    /// A frame for user pages, above the direct map while there are any left.
+    /// The kernel reaches it through `kmap`.
+    pub fn alloc_user(&self) -> Result<*mut u8, OutOfMemory> {
+        let mut state = self.state.borrow_mut();
+        let Some(index) = state.find_free(state.direct_frames..state.frames, state.next_high)
+        else {
+            drop(state);
+            return self.alloc();
+        };
+
+        state.set_used(index, true);
+        state.free -= 1;
+        state.next_high = index + 1;
+        Ok(state.address(index))
+    }
+
+    /// `count` physically contiguous frames, the first one aligned to `align` bytes
+    pub fn alloc_contiguous(&self, count: usize, align: usize) -> Result<*mut u8, OutOfMemory> {
+        debug_assert!(align.is_power_of_two());
         let mut state = self.state.borrow_mut();
+        let align = align.max(PAGE_SIZE) / PAGE_SIZE;
+
+        let mut index = state.first_frame.div_ceil(align) * align - state.first_frame;
+        while index + count <= state.direct_frames {
+            match (index..index + count).rev().find(|&i| state.is_used(i)) {
+                Some(used) => {
+                    let frame = state.first_frame + used + 1;
+                    index = frame.div_ceil(align) * align - state.first_frame;
+                }
+                None => {
+                    for i in index..index + count {
+                        state.set_used(i, true);
+                    }
+                    state.free -= count;
+                    return Ok(state.address(index));
+                }
+            }
+        }
 
-        unsafe {
-            if !state.freed.is_null() {
-                let res = state.freed;
-                state.freed = *state.freed as *mut *mut u8;
-                res as _
-            } else {
-                let res = state.current;
-                state.current = state.current.byte_add(N);
-                if state.current > state.end {
-                    panic!(
-                        "OOM: the arena is not enough to allocate\n\
-                        chunk_size: {:x}\n\
-                        arena_current: {:x?}\n\
-                        arena_end: {:x?}",
-                        N, state.current, state.end
-                    );
+        Err(OutOfMemory)
+    }
+
+    /// Drops a reference to the frame, it is freed once nothing else refers to it
+    pub fn free(&self, frame: *mut u8) {
+        {
+            let mut shared = self.shared.borrow_mut();
+            if let Some(refs) = shared.get_mut(&(frame as usize)) {
+                *refs -= 1;
+                if *refs == 0 {
+                    shared.remove(&(frame as usize));
                 }
-                res as _
+                return;
             }
         }
+        self.free_contiguous(frame, 1);
+    }
+
+    /// Adds a reference to an allocated frame, it takes one more `free` to release it
+    pub fn share(&self, frame: *mut u8) {
+        debug_assert!({
+            let state = self.state.borrow();
+            state.is_used(state.index(frame))
+        });
+        *self.shared.borrow_mut().entry(frame as usize).or_insert(0) += 1;
+    }
+
+    pub fn is_shared(&self, frame: *mut u8) -> bool {
+        self.shared.borrow().contains_key(&(frame as usize))
     }
 
-    pub fn free(&self, pointer: *mut u8) {
+    pub fn free_contiguous(&self, frame: *mut u8, count: usize) {
         let mut state = self.state.borrow_mut();
-        let p = pointer as *mut *mut u8;
+        let first = state.index(frame);
+
+        for index in first..first + count {
+            if !state.is_used(index) {
+                panic!("Double free of the frame {:x?}", state.address(index));
+            }
+            state.set_used(index, false);
+        }
+        state.free += count;
+        if first < state.direct_frames {
+            state.next = state.next.min(first);
+        } else {
+            state.next_high = state.next_high.min(first);
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let state = self.state.borrow();
+        FrameStats {
+            total: state.total,
+            free: state.free,
+            used: state.total - state.free,
+            high: state.high,
+        }
+    }
+}
+
+impl FrameAllocatorState {
+    /// First free frame in `range`, looking from `from` on and then from the
+    /// start of the range. `range` starts and ends at words of the bitmap or
+    /// at the last frame.
+    fn find_free(&self, range: Range<usize>, from: usize) -> Option<usize> {
+        let words = range.start / 32..range.end.div_ceil(32);
+        let from = from.clamp(range.start, range.end) / 32;
+        let word = (from..words.end)
+            .chain(words.start..from)
+            .find(|&w| self.bitmap[w] != u32::MAX)?;
+        // the tail bits past the last frame are always set
+        Some(word * 32 + (!self.bitmap[word]).trailing_zeros() as usize)
+    }
+
+    fn index(&self, frame: *mut u8) -> usize {
+        let addr = frame as usize;
+        assert!(
+            addr.is_multiple_of(PAGE_SIZE) && addr / PAGE_SIZE >= self.first_frame,
+            "Not a frame of the allocator: {:x?}",
+            frame
+        );
+
+        let index = addr / PAGE_SIZE - self.first_frame;
+        assert!(
+            index < self.frames,
+            "Not a frame of the allocator: {:x?}",
+            frame
+        );
+        index
+    }
+
+    fn address(&self, index: usize) -> *mut u8 {
+        ((self.first_frame + index) * PAGE_SIZE) as _
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / 32] & (1 << (index % 32)) != 0
+    }
 
-        unsafe {
-            *p = state.freed as *mut u8;
-            state.freed = p;
+    fn set_used(&mut self, index: usize, used: bool) {
+        if used {
+            self.bitmap[index / 32] |= 1 << (index % 32);
+        } else {
+            self.bitmap[index / 32] &= !(1 << (index % 32));
         }
     }
 }
@@ -1,5 +1,22 @@
+use bitflags::bitflags;
+
 use crate::paging::{HugePage, Page};
 
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageFlags: u32 {
+        const PRESENT = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const USER = 1 << 2;
+        // bits 9..11 are free for the OS
+        /// The frame belongs to the address space and is freed with the mapping
+        const OWNED = 1 << 9;
+        /// Read only view of a writable page shared with another address space,
+        /// the frame is copied on the first write
+        const COW = 1 << 10;
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct PageTableEntry(pub u32);
@@ -12,17 +29,17 @@ pub type PageTable = [PageTableEntry; 1024];
 pub type PageDirectory = [PageDirectoryEntry; 1024];
 
 impl PageTableEntry {
-    pub fn new(page: *mut Page, present: bool, rw: bool, us: bool) -> Self {
-        debug_assert!(page as u32 & ((1 << 13) - 1) == 0);
-        Self(page as u32 & (!0 << 12) | (us as u32) << 2 | (rw as u32) << 1 | (present as u32))
+    pub fn with_flags(frame: usize, flags: PageFlags) -> Self {
+        debug_assert!(frame & ((1 << 12) - 1) == 0);
+        Self(frame as u32 | flags.bits())
     }
 
     pub const fn empty() -> Self {
         Self(0)
     }
 
-    pub const fn is_empty(&self) -> bool {
-        self.0 == 0
+    pub fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.0)
     }
 
     pub fn page_addr(&self) -> *mut Page {
@@ -32,19 +49,11 @@ impl PageTableEntry {
     pub fn present(&self) -> bool {
         self.0 & 0b1 != 0
     }
-
-    pub fn rw(&self) -> bool {
-        self.0 & 0b10 != 0
-    }
-
-    pub fn us(&self) -> bool {
-        self.0 & 0b100 != 0
-    }
 }
 
 impl PageDirectoryEntry {
     pub fn new_4kb(pt: *mut PageTable, present: bool, rw: bool, us: bool) -> Self {
-        debug_assert!(pt as u32 & ((1 << 13) - 1) == 0);
+        debug_assert!(pt as u32 & ((1 << 12) - 1) == 0);
         Self(((pt as u32) & (!0 << 12)) | (us as u32) << 2 | (rw as u32) << 1 | (present as u32))
     }
 
@@ -63,10 +72,6 @@ impl PageDirectoryEntry {
         Self(0)
     }
 
-    pub const fn is_empty(&self) -> bool {
-        self.0 == 0
-    }
-
     pub fn pt_addr(&self) -> *mut PageTable {
         (self.0 & (!0 << 12)) as _
     }
@@ -0,0 +1,85 @@
+use core::ptr;
+
+use utils::nullsync;
+
+use super::{
+    DIRECT_MAP_SIZE, FB_WINDOW, FB_WINDOW_SIZE, PAGE_SIZE, Page, PageDirectoryEntry, PageFlags,
+    PageTable, PageTableEntry, invlpg, kernel_page_directory, phys_to_virt, virt_to_phys,
+};
+
+/// Frames the direct map does not reach are mapped here one page at a time
+const KMAP_WINDOW: usize = FB_WINDOW + FB_WINDOW_SIZE;
+const KMAP_SLOTS: usize = 1024;
+
+#[repr(C, align(4096))]
+struct KmapTable(PageTable);
+
+/// Page table of the window, shared by every address space through the kernel half
+static mut KMAP_TABLE: KmapTable = KmapTable([PageTableEntry::empty(); KMAP_SLOTS]);
+/// One bit per page of the window, set while it is in use
+static SLOTS: nullsync::RefCell<[u32; KMAP_SLOTS / 32]> =
+    nullsync::RefCell::new([0; KMAP_SLOTS / 32]);
+
+/// Kernel view of a frame, the temporary mapping goes away on drop
+pub struct KMap {
+    page: *mut Page,
+    slot: Option<usize>,
+}
+
+/// Puts the window into the kernel half, before any address space copies it
+pub(super) fn init() {
+    unsafe {
+        let table = ptr::addr_of_mut!(KMAP_TABLE);
+        (*kernel_page_directory())[KMAP_WINDOW >> 22] =
+            PageDirectoryEntry::new_4kb(virt_to_phys(table) as _, true, true, false);
+    }
+}
+
+/// Maps `frame` into the kernel, frames in the direct map need no slot
+pub fn kmap(frame: usize) -> KMap {
+    debug_assert!(frame.is_multiple_of(PAGE_SIZE));
+    if frame < DIRECT_MAP_SIZE {
+        return KMap {
+            page: phys_to_virt(frame),
+            slot: None,
+        };
+    }
+
+    let slot = {
+        let mut slots = SLOTS.borrow_mut();
+        let word = slots
+            .iter()
+            .position(|&word| word != u32::MAX)
+            .expect("No free temporary mapping");
+        let bit = (!slots[word]).trailing_zeros() as usize;
+        slots[word] |= 1 << bit;
+        word * 32 + bit
+    };
+
+    unsafe {
+        (*ptr::addr_of_mut!(KMAP_TABLE)).0[slot] =
+            PageTableEntry::with_flags(frame, PageFlags::PRESENT | PageFlags::WRITABLE);
+    }
+    KMap {
+        page: (KMAP_WINDOW + slot * PAGE_SIZE) as _,
+        slot: Some(slot),
+    }
+}
+
+impl KMap {
+    pub fn as_ptr(&self) -> *mut Page {
+        self.page
+    }
+}
+
+impl Drop for KMap {
+    fn drop(&mut self) {
+        let Some(slot) = self.slot else {
+            return;
+        };
+
+        unsafe { (*ptr::addr_of_mut!(KMAP_TABLE)).0[slot] = PageTableEntry::empty() };
+        invlpg(self.page as usize);
+        SLOTS.borrow_mut()[slot / 32] &= !(1 << (slot % 32));
+    }
+}
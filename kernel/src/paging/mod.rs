@@ -1,173 +1,122 @@
+mod address_space;
 mod allocator;
 mod entries;
+mod kmap;
 
 use core::arch::asm;
-use core::array;
 
-pub use allocator::POOL4K;
+use crate::boot_info::boot_info;
+
+pub use address_space::{AddressSpace, Region, RegionKind};
+pub use allocator::{FRAMES, OutOfMemory};
 pub use entries::PageDirectoryEntry;
+pub use entries::PageFlags;
 pub use entries::PageTableEntry;
 
-pub use entries::PageDirectory;
+pub use entries::{PageDirectory, PageTable};
+pub use kmap::{KMap, kmap};
 
 pub const PAGE_SIZE: usize = 4 * 1024;
 pub const HUGE_PAGE_SIZE: usize = 4 * 1024 * 1024;
 pub type Page = [u8; PAGE_SIZE];
 pub type HugePage = [u8; HUGE_PAGE_SIZE];
 
-pub fn init_paging_regs() {
-    unsafe {
-        asm!(
-            "mov eax, cr0",
-            "and eax, ~(1 << 16)",
-            "mov cr0, eax",
-            "mov eax, cr4",
-            "or eax, 1 << 4",
-            "mov cr4, eax",
-        )
-    }
-}
+/// Start of the kernel half of every address space, user space gets everything below
+pub const KERNEL_BASE: usize = 0xc000_0000;
+/// Physical memory is mapped at `KERNEL_BASE` up to this size, frames above
+/// it are reached through `kmap`
+pub const DIRECT_MAP_SIZE: usize = 896 * 1024 * 1024;
 
-#[inline(always)]
-pub fn enable_paging(pd: *mut PageDirectory) {
-    unsafe { asm!("mov cr3, {}", "mov eax, cr0", "or eax, 1 << 31", "mov cr0, eax", in(reg) pd) }
-}
+const FB_WINDOW: usize = KERNEL_BASE + DIRECT_MAP_SIZE;
+const FB_WINDOW_SIZE: usize = 64 * 1024 * 1024;
 
-#[inline(always)]
-pub fn disable_paging() {
-    unsafe {
-        asm!("mov eax, cr0", "and eax, ~(1 << 31)", "mov cr0, eax");
-    }
-}
+const KERNEL_PDE: usize = KERNEL_BASE >> 22;
 
-pub fn init_fb_paging(pd: *mut PageDirectory) {
-    let start_addr = (unsafe { crate::framebuffer_addr as usize } & (0x3ff * HUGE_PAGE_SIZE));
-    let end_addr = {
-        let addr = unsafe {
-            crate::framebuffer_addr as usize
-                + crate::framebuffer_width as usize * crate::framebuffer_height as usize
-        };
-        (addr + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1)
-    };
-
-    for i in (start_addr >> 22)..(end_addr >> 22) {
-        unsafe {
-            (*pd)[i] = PageDirectoryEntry::new_4mb((i * HUGE_PAGE_SIZE) as _, true, true, false)
-        }
-    }
+unsafe extern "C" {
+    static mut boot_page_directory: PageDirectory;
 }
 
-pub fn init_kernel_paging() -> *mut PageDirectory {
-    let pd = POOL4K.alloc() as *mut PageDirectory;
+pub fn phys_to_virt<T>(phys: usize) -> *mut T {
+    debug_assert!(phys < DIRECT_MAP_SIZE);
+    (phys + KERNEL_BASE) as _
+}
 
-    let pt0 = unsafe {
-        *pd = array::from_fn(|_| PageDirectoryEntry::empty());
-        (*pd)[0] = PageDirectoryEntry::new_4kb(POOL4K.alloc() as _, true, true, true);
-        &mut *(*pd)[0].pt_addr()
-    };
+pub fn virt_to_phys<T>(virt: *const T) -> usize {
+    debug_assert!((KERNEL_BASE..FB_WINDOW).contains(&(virt as usize)));
+    virt as usize - KERNEL_BASE
+}
 
-    *pt0 = array::from_fn(|i| PageTableEntry::new((i * PAGE_SIZE) as _, true, true, false));
+/// Page directory the entry code enabled paging with. Its kernel half is
+/// copied into every new address space, so it must not change after boot.
+pub fn kernel_page_directory() -> *mut PageDirectory {
+    &raw mut boot_page_directory
+}
 
-    init_fb_paging(pd);
-    init_paging_regs();
-    pd
+#[inline(always)]
+pub fn load_page_directory(pd: *mut PageDirectory) {
+    unsafe { asm!("mov cr3, {}", in(reg) virt_to_phys(pd)) }
 }
 
-pub fn init_code_pages(pd: *mut PageDirectory, phys: *mut u8) {
-    unsafe {
-        let pde2 = &mut (*pd)[2];
-        if pde2.is_empty() {
-            let pt = POOL4K.alloc() as *mut [PageTableEntry; 1024];
-            for i in 0..16 {
-                (*pt)[i] =
-                    PageTableEntry::new((phys as usize + i * PAGE_SIZE) as _, true, true, true)
-            }
-
-            for i in 16..1024 {
-                (*pt)[i] = PageTableEntry::empty();
-            }
-
-            (*pde2) = PageDirectoryEntry::new_4kb(pt as _, true, true, true);
-        }
-    }
+#[inline(always)]
+pub fn invlpg(virt: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) virt) }
 }
 
-pub fn init_stack_pages(pd: *mut PageDirectory) {
+/// Drops the identity mapping the entry code switched to the higher half
+/// with and sets up the window for temporary mappings
+pub fn init() {
+    let pd = kernel_page_directory();
     unsafe {
-        let pde1 = &mut (*pd)[1];
-
-        if pde1.is_empty() {
-            let pt = POOL4K.alloc() as *mut [PageTableEntry; 1024];
-            for i in 0..1023 {
-                (*pt)[i] = PageTableEntry::new(0 as _, false, true, true);
-            }
-            (*pt)[1023] = PageTableEntry::new((POOL4K.alloc()) as _, true, true, true);
-            (*pde1) = PageDirectoryEntry::new_4kb(pt as _, true, true, true);
-        } else if pde1.present() && !pde1.pt_addr().is_null() {
-            for pt in (&mut *pde1.pt_addr()).iter_mut() {
-                if !pt.is_empty() && pt.present() {
-                    POOL4K.free(pt.page_addr() as _);
-                    *pt = PageTableEntry::new((POOL4K.alloc()) as _, true, true, true);
-                }
-            }
-        }
+        (*pd)[0] = PageDirectoryEntry::empty();
     }
+    kmap::init();
+    load_page_directory(pd);
 }
 
-pub fn delete_process_pages(pd: *mut PageDirectory) {
-    let pt1 = unsafe { &mut *(*pd)[1].pt_addr() };
-    let pt2 = unsafe { &mut *(*pd)[2].pt_addr() };
-
-    for pte in pt1.iter() {
-        if !pte.page_addr().is_null() && pte.present() {
-            POOL4K.free(pte.page_addr() as _);
+/// Maps the framebuffer right after the direct map, returns its virtual address
+pub fn map_framebuffer() -> *mut u32 {
+    let fb = &boot_info().framebuffer;
+    let start_addr = fb.addr & !(HUGE_PAGE_SIZE - 1);
+    let end_addr = (fb.addr + fb.size() + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1);
+    assert!(
+        end_addr - start_addr <= FB_WINDOW_SIZE,
+        "Framebuffer does not fit in its window"
+    );
+
+    let pd = kernel_page_directory();
+    for (i, addr) in (start_addr..end_addr).step_by(HUGE_PAGE_SIZE).enumerate() {
+        unsafe {
+            (*pd)[(FB_WINDOW >> 22) + i] = PageDirectoryEntry::new_4mb(addr as _, true, true, false)
         }
     }
-    POOL4K.free(&raw mut *pt1 as _);
-    unsafe {
-        (*pd)[1] = PageDirectoryEntry::empty();
-    }
 
-    for i in 16..1024 {
-        if !pt2[i].page_addr().is_null() && pt2[i].present() {
-            POOL4K.free(pt2[i].page_addr() as _);
-        }
-    }
+    (FB_WINDOW + fb.addr - start_addr) as _
 }
 
-pub fn init_args_pages(pd: *mut PageDirectory, user_args: &[&[u8]]) -> (u32, *const *const u8) {
-    const START: usize = 0x810_000;
+fn page_table(pde: &PageDirectoryEntry) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(pde.pt_addr() as usize) }
+}
 
-    debug_assert!(user_args.len() <= 1008);
-    let argc = user_args.len();
+fn alloc_page<T>() -> Result<*mut T, OutOfMemory> {
+    Ok(phys_to_virt(FRAMES.alloc()? as usize))
+}
 
-    let pt2 = unsafe { &mut *(*pd)[2].pt_addr() };
-    let argv_page = POOL4K.alloc() as *mut *mut u8;
-    pt2[16] = PageTableEntry::new(argv_page as _, true, true, true);
+fn free_page<T>(page: *mut T) {
+    FRAMES.free(virt_to_phys(page) as _);
+}
 
-    for (i, string) in user_args.iter().enumerate() {
-        debug_assert!(string.len() <= PAGE_SIZE);
-        let page = POOL4K.alloc();
-        pt2[17 + i] = PageTableEntry::new(page as _, true, true, true);
+fn new_page_directory() -> Result<*mut PageDirectory, OutOfMemory> {
+    let pd = alloc_page::<PageDirectory>()?;
+    let kernel_pd = kernel_page_directory();
 
+    for i in 0..1024 {
         unsafe {
-            page.copy_from(string.as_ptr(), string.len());
-            *argv_page.add(i) = (START + (i + 1) * PAGE_SIZE) as _;
+            (*pd)[i] = if i < KERNEL_PDE {
+                PageDirectoryEntry::empty()
+            } else {
+                (*kernel_pd)[i]
+            };
         }
     }
-
-    (argc as _, START as _)
-}
-
-pub fn enable_stack_pages(pd: *mut PageDirectory, address: u32, cur_page_ind: &mut usize) {
-    let pt1 = unsafe { &mut *(*pd)[1].pt_addr() };
-    let index = (address as usize >> 12) & 0x3ff;
-
-    for i in index..*cur_page_ind {
-        pt1[i] = PageTableEntry::new(POOL4K.alloc() as _, true, pt1[i].rw(), pt1[i].us());
-    }
-
-    if index < *cur_page_ind {
-        *cur_page_ind = index;
-    }
+    Ok(pd)
 }
@@ -1,6 +1,6 @@
 use core::panic::PanicInfo;
 
-use crate::{TBW, x86_utils::cli};
+use crate::{TBW, device_manager::DEVICES, x86_utils::cli};
 use utils::io::Write;
 
 #[panic_handler]
@@ -13,5 +13,10 @@ fn panic(info: &PanicInfo) -> ! {
     write!(tbw, "KERNEL PANIC").unwrap();
     tbw.set_next_fg(0x00ffffff);
     writeln!(tbw, "]\n{}", info.message()).unwrap();
+
+    // interrupts are off, the serial log is sent by polling
+    let mut serial = &DEVICES.com1;
+    writeln!(serial, "[KERNEL PANIC]\n{}", info).unwrap();
+    serial.flush();
     loop {}
 }
@@ -1,8 +1,7 @@
 use crate::{
     interrupts::InterruptContext,
-    paging,
-    process::get_cur_process,
-    x86_utils::{sti, tsc_sleep},
+    paging::{PageFlags, RegionKind},
+    process::{self, ExitStatus, Fault, STACK_LIMIT, get_cur_process},
 };
 
 use utils::io::Write;
@@ -10,7 +9,7 @@ use utils::io::Write;
 pub fn user_global_handler(ctx: &mut InterruptContext) {
     match ctx.vector {
         0xe => pagefault_handler(ctx),
-        _ => unexpected_error_handler(ctx),
+        _ => unexpected_error_handler(),
     }
 }
 
@@ -23,59 +22,71 @@ pub fn pagefault_handler(ctx: &mut InterruptContext) {
         SOE,
         UB,
         GuardPage,
+        CopyOnWrite,
     }
 
-    let user_err = match ctx.cr2 {
+    let addr = ctx.cr2 as usize;
+    let not_present = ctx.errcode & 1 == 0;
+    let write = ctx.errcode & (1 << 1) != 0;
+    let space = &get_cur_process().space;
+    let in_stack = space
+        .region(addr)
+        .is_some_and(|region| region.kind == RegionKind::Stack);
+    let cow = space
+        .entry(addr)
+        .is_some_and(|pte| pte.flags().contains(PageFlags::COW));
+
+    let user_err = match addr {
         0..0x200000 => UserErr::NPE,
-        0x200000..0x400000 => UserErr::SOE,
-        0x400000..0x800000 => UserErr::GuardPage,
+        0x200000..STACK_LIMIT => UserErr::SOE,
+        _ if in_stack && not_present => UserErr::GuardPage,
+        _ if cow && write => UserErr::CopyOnWrite,
         _ => UserErr::UB,
     };
 
-    let handler = match user_err {
-        UserErr::NPE => npe_handler,
-        UserErr::SOE => soe_handler,
-        UserErr::UB => unexpected_error_handler,
-        UserErr::GuardPage => stack_expand_handler,
-    };
-    handler(ctx)
+    match user_err {
+        UserErr::NPE => npe_handler(),
+        UserErr::SOE => soe_handler(),
+        UserErr::UB => ub_handler(),
+        UserErr::GuardPage => stack_expand_handler(ctx),
+        UserErr::CopyOnWrite => copy_on_write_handler(ctx),
+    }
 }
 
-pub fn npe_handler(ctx: &mut InterruptContext) {
+pub fn npe_handler() {
     let process = get_cur_process();
-    writeln!(process.tbw, "NPE").unwrap();
-    process.kill();
-    sti();
-    loop {}
+    writeln!(process.tty, "NPE").unwrap();
+    process::exit(ExitStatus::Killed(Fault::NPE))
 }
 
-pub fn soe_handler(ctx: &mut InterruptContext) {
+pub fn soe_handler() {
     let process = get_cur_process();
-    writeln!(process.tbw, "SOE").unwrap();
-    process.kill();
-    sti();
-    loop {}
+    writeln!(process.tty, "SOE").unwrap();
+    process::exit(ExitStatus::Killed(Fault::SOE))
 }
 
-pub fn ub_handler(ctx: &mut InterruptContext) {
+pub fn ub_handler() {
     let process = get_cur_process();
-    writeln!(process.tbw, "UB").unwrap();
-    process.kill();
-    sti();
-    loop {}
+    writeln!(process.tty, "UB").unwrap();
+    process::exit(ExitStatus::Killed(Fault::UB))
 }
 
 pub fn stack_expand_handler(ctx: &mut InterruptContext) {
     let process = get_cur_process();
-    paging::disable_paging();
-    paging::enable_stack_pages(process.pd, ctx.cr2, &mut process.stack_pte_ind);
-    paging::enable_paging(process.pd);
+    if process.grow_stack(ctx.cr2 as usize).is_err() {
+        process.kill_oom();
+    }
+}
+
+pub fn copy_on_write_handler(ctx: &mut InterruptContext) {
+    let process = get_cur_process();
+    if process.space.copy_on_write(ctx.cr2 as usize).is_err() {
+        process.kill_oom();
+    }
 }
 
-pub fn unexpected_error_handler(ctx: &mut InterruptContext) {
+pub fn unexpected_error_handler() {
     let process = get_cur_process();
-    writeln!(process.tbw, "Unexpected error").unwrap();
-    process.kill();
-    sti();
-    loop {}
+    writeln!(process.tty, "Unexpected error").unwrap();
+    process::exit(ExitStatus::Killed(Fault::Unexpected))
 }
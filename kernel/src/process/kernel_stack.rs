@@ -0,0 +1,130 @@
+use core::{arch::naked_asm, mem};
+
+use crate::{
+    interrupts::{self, InterruptContext},
+    paging::{self, FRAMES, OutOfMemory, PAGE_SIZE},
+};
+
+const STACK_FRAMES: usize = 4;
+pub const KERNEL_STACK_SIZE: usize = STACK_FRAMES * PAGE_SIZE;
+
+/// Stack the interrupts from user space run on, one per process. A handler
+/// can switch to another stack and continue when it is switched back to.
+pub struct KernelStack {
+    base: *mut u8,
+    /// Stack pointer saved while the stack is switched out
+    esp: usize,
+}
+
+/// What `switch_stack` pushes before it leaves a stack
+#[repr(C)]
+struct SwitchFrame {
+    edi: u32,
+    esi: u32,
+    ebx: u32,
+    ebp: u32,
+    ret: u32,
+}
+
+impl KernelStack {
+    pub fn new() -> Result<Self, OutOfMemory> {
+        let frame = FRAMES.alloc_contiguous(STACK_FRAMES, PAGE_SIZE)?;
+        let base = paging::phys_to_virt::<u8>(frame as usize);
+
+        Ok(Self {
+            base,
+            esp: base as usize + KERNEL_STACK_SIZE,
+        })
+    }
+
+    pub fn top(&self) -> usize {
+        self.base as usize + KERNEL_STACK_SIZE
+    }
+
+    /// Makes the first switch to this stack return to user space with `ctx`
+    pub fn start_with(&mut self, ctx: &InterruptContext) {
+        let ctx_addr = self.top() - mem::size_of::<InterruptContext>();
+        let frame_addr = ctx_addr - mem::size_of::<SwitchFrame>();
+
+        unsafe {
+            (ctx_addr as *mut InterruptContext).write(ctx.clone());
+            (frame_addr as *mut SwitchFrame).write(SwitchFrame {
+                edi: 0,
+                esi: 0,
+                // pop_ctx takes the context in ebx
+                ebx: ctx_addr as _,
+                ebp: 0,
+                ret: enter_user as usize as _,
+            });
+        }
+        self.esp = frame_addr;
+    }
+
+    /// Makes the first switch to this stack call `entry`
+    pub fn start_kernel(&mut self, entry: extern "C" fn() -> !) {
+        // as if `entry` was called with a 16 byte aligned stack
+        let entry_esp = self.top() - 16 - mem::size_of::<u32>();
+        let frame_addr = entry_esp - mem::size_of::<SwitchFrame>();
+
+        unsafe {
+            (frame_addr as *mut SwitchFrame).write(SwitchFrame {
+                edi: 0,
+                esi: 0,
+                ebx: 0,
+                ebp: 0,
+                ret: entry as usize as _,
+            });
+        }
+        self.esp = frame_addr;
+    }
+
+    /// Continues on `to`, returns once something switches back to `self`
+    pub fn switch_to(&mut self, to: &KernelStack) {
+        unsafe { switch_stack(&mut self.esp, to.esp) }
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        FRAMES.free_contiguous(paging::virt_to_phys(self.base) as _, STACK_FRAMES);
+    }
+}
+
+/// Leaves the boot stack for good
+pub fn switch_from_boot(to: &KernelStack) -> ! {
+    let mut boot_esp = 0;
+    unsafe { switch_stack(&mut boot_esp, to.esp) };
+    unreachable!("Switched back to the boot stack")
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stack(from: *mut usize, to: usize) {
+    naked_asm!(
+        "mov eax, [esp + 4]",
+        "mov edx, [esp + 8]",
+        // callee saved registers, see SwitchFrame
+        "push ebp",
+        "push ebx",
+        "push esi",
+        "push edi",
+        "mov [eax], esp",
+        "mov esp, edx",
+        "pop edi",
+        "pop esi",
+        "pop ebx",
+        "pop ebp",
+        "ret",
+    )
+}
+
+/// Where a new process starts in the kernel, ebx points to its user context
+#[unsafe(naked)]
+extern "C" fn enter_user() {
+    naked_asm!(
+        "and esp, ~15",
+        "call {finish}",
+        "jmp {pop_ctx}",
+        finish = sym super::finish_switch,
+        pop_ctx = sym interrupts::pop_ctx,
+    )
+}
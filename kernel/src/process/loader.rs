@@ -0,0 +1,90 @@
+use crate::{
+    elf::{Elf, ElfError, PF_W, PF_X, Segment},
+    paging::{self, AddressSpace, OutOfMemory, PAGE_SIZE, PageFlags, Region, RegionKind},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum LoadError {
+    OutOfMemory,
+    BadElf(ElfError),
+    /// A segment overlaps the stack, the arguments or the kernel
+    BadLayout,
+}
+
+impl From<OutOfMemory> for LoadError {
+    fn from(_: OutOfMemory) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        Self::BadElf(err)
+    }
+}
+
+/// Maps the PT_LOAD segments of `image` into `space`, returns the entry point.
+/// Every user page between `start` and `end` is free for the program.
+pub fn load(
+    space: &mut AddressSpace,
+    image: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<usize, LoadError> {
+    let elf = Elf::parse(image)?;
+
+    for segment in elf.segments() {
+        if segment.vaddr < start || segment.vaddr + segment.memsz > end {
+            return Err(LoadError::BadLayout);
+        }
+    }
+
+    for segment in elf.segments().filter(|segment| segment.memsz != 0) {
+        load_segment(space, &segment)?;
+    }
+    Ok(elf.entry())
+}
+
+fn load_segment(space: &mut AddressSpace, segment: &Segment) -> Result<(), LoadError> {
+    let mut flags = PageFlags::USER;
+    if segment.flags & PF_W != 0 {
+        flags |= PageFlags::WRITABLE;
+    }
+
+    let start = segment.vaddr & !(PAGE_SIZE - 1);
+    let end = (segment.vaddr + segment.memsz).next_multiple_of(PAGE_SIZE);
+
+    for page in (start..end).step_by(PAGE_SIZE) {
+        // segments may share a page, it gets the permissions of both
+        let kernel_page = match space.entry(page) {
+            Some(pte) => {
+                space.protect(page, pte.flags() | flags);
+                paging::kmap(pte.page_addr() as usize)
+            }
+            None => space.map_zeroed(page, flags)?,
+        };
+
+        // the part of the file data that falls into this page
+        let from = page.max(segment.vaddr);
+        let to = (page + PAGE_SIZE).min(segment.vaddr + segment.data.len());
+        if from < to {
+            let data = &segment.data[from - segment.vaddr..to - segment.vaddr];
+            unsafe {
+                (kernel_page.as_ptr() as *mut u8)
+                    .add(from - page)
+                    .copy_from_nonoverlapping(data.as_ptr(), data.len());
+            }
+        }
+    }
+
+    space.add_region(Region {
+        start: segment.vaddr,
+        end: segment.vaddr + segment.memsz,
+        kind: if segment.flags & PF_X != 0 {
+            RegionKind::Code
+        } else {
+            RegionKind::Data
+        },
+    });
+    Ok(())
+}
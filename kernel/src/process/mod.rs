@@ -1,135 +1,509 @@
 mod errors;
+mod kernel_stack;
+mod loader;
+pub mod scheduler;
+mod table;
+mod tty;
+mod wait_queue;
 use utils::{
-    nullsync,
+    io::Write,
     textbuffer::{TextBufferRegion, TextBufferWritter},
 };
 
 use crate::{
     TBW,
+    boot_info::boot_info,
+    fs::{self, FdTable, FsError, OpenFlags},
     gdt::{USER_CS, USER_DS},
-    interrupts::{self, InterruptContext},
-    paging::{self},
+    info,
+    interrupts::InterruptContext,
+    paging::{AddressSpace, KERNEL_BASE, OutOfMemory, PAGE_SIZE, PageFlags, Region, RegionKind},
     process,
-    x86_utils::EFlags,
+    tss::TSS,
+    x86_utils::{EFlags, cli, hlt, sti},
 };
-use core::arch::asm;
+use alloc::vec::Vec;
+use core::{ptr, str};
 
+pub use kernel_stack::KernelStack;
+pub use loader::LoadError;
 pub use process::errors::user_global_handler;
+pub use scheduler::{SchedInfo, scheduler};
+pub use table::{Pid, ProcessTable};
+pub use tty::Tty;
+pub use wait_queue::WaitQueue;
 
-pub const VIRT_START: *mut u8 = 0x800_000 as _;
+/// Programs are linked above the stack top
+pub const STACK_TOP: usize = 0x800_000;
+/// The stack grows down on page faults until this address
+pub const STACK_LIMIT: usize = 0x400_000;
+const ARGS_START: usize = KERNEL_BASE - ARGS_SIZE;
+const ARGS_SIZE: usize = (MAX_ARGS + 1) * PAGE_SIZE;
+pub const MAX_ARGS: usize = 1008;
 
-pub static mut PROCESSES: nullsync::LazyCell<[Process; 4]> = nullsync::LazyCell::new(|| {
-    [
-        template_process(0x20000, 0, 0, 2, 2),
-        template_process(0x30000, 1, 0, 2, 2),
-        template_process(0x40000, 0, 1, 2, 2),
-        template_process(0x50000, 1, 1, 2, 2),
-    ]
-});
-pub static mut CUR_PROCCESS: usize = 0;
+static mut PROCESSES: ProcessTable = ProcessTable::new();
+
+pub fn processes() -> &'static mut ProcessTable {
+    unsafe { &mut *ptr::addr_of_mut!(PROCESSES) }
+}
 
 pub fn get_cur_process() -> &'static mut Process {
-    unsafe { &mut PROCESSES[CUR_PROCCESS] }
+    processes().current().expect("No process is running")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SpawnError {
+    NotFound,
+    /// The program file could not be read
+    Read(FsError),
+    Load(LoadError),
+}
+
+impl From<FsError> for SpawnError {
+    fn from(err: FsError) -> Self {
+        match err {
+            FsError::NotFound => Self::NotFound,
+            err => Self::Read(err),
+        }
+    }
+}
+
+impl From<LoadError> for SpawnError {
+    fn from(err: LoadError) -> Self {
+        Self::Load(err)
+    }
+}
+
+impl From<OutOfMemory> for SpawnError {
+    fn from(err: OutOfMemory) -> Self {
+        Self::Load(err.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Runnable,
+    /// Waiting in a `WaitQueue` or in `nanosleep`
+    Sleeping,
+    /// Exited, kept until the parent collects the status
+    Zombie(ExitStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(u32),
+    Killed(Fault),
+}
+
+/// Reason the kernel killed a process, the value is reported in the wait status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Fault {
+    NPE = 1,
+    SOE = 2,
+    UB = 3,
+    OutOfMemory = 4,
+    Unexpected = 5,
+}
+
+impl ExitStatus {
+    /// Wait status seen by user space: the fault in the low 7 bits, or 0 and
+    /// the exit code in bits 8..16
+    pub fn encode(self) -> u32 {
+        match self {
+            Self::Exited(code) => (code & 0xff) << 8,
+            Self::Killed(fault) => fault as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NoChild;
+
+/// Parents waiting for a child to exit
+pub static CHILD_EXITED: WaitQueue = WaitQueue::new();
+
+static mut IDLE: Option<KernelStack> = None;
+
+/// Starts the program at the absolute path `name`, or the boot module `name`,
+/// as a new process
+pub fn spawn(
+    name: &[u8],
+    args: &[&[u8]],
+    tty: Tty,
+    parent: Option<Pid>,
+) -> Result<Pid, SpawnError> {
+    let file;
+    let program = if name.starts_with(b"/") {
+        file = read_program(name)?;
+        &file[..]
+    } else {
+        boot_info().module(name).ok_or(SpawnError::NotFound)?.data()
+    };
+
+    let mut process = Process::new(tty)?;
+    process.parent = parent;
+    process.init(program, args)?;
+    Ok(processes().insert(process))
+}
+
+/// Content of the program file at `path`
+fn read_program(path: &[u8]) -> Result<Vec<u8>, SpawnError> {
+    let path = str::from_utf8(path).map_err(|_| FsError::InvalidPath)?;
+    let file = fs::open(path, OpenFlags::empty())?;
+
+    let mut data = Vec::new();
+    let size = file.stat().size as usize;
+    data.try_reserve_exact(size)
+        .map_err(|_| LoadError::OutOfMemory)?;
+    data.resize(size, 0);
+    let mut len = 0;
+    while len < size {
+        match file.read(&mut data[len..])? {
+            0 => break,
+            count => len += count,
+        }
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Collects the status of an exited child of `parent`, `pid` picks the child.
+/// Returns None while the matching children are still alive.
+pub fn wait(parent: Pid, pid: Option<Pid>) -> Result<Option<(Pid, ExitStatus)>, NoChild> {
+    let processes = processes();
+    let is_child = |process: &&Process| {
+        process.parent == Some(parent) && pid.is_none_or(|pid| process.pid == pid)
+    };
+    if !processes.iter().any(|process| is_child(&process)) {
+        return Err(NoChild);
+    }
+
+    let zombie = processes
+        .iter()
+        .filter(is_child)
+        .find_map(|process| match process.state {
+            State::Zombie(status) => Some((process.pid, status)),
+            State::Runnable | State::Sleeping => None,
+        });
+    if let Some((pid, _)) = zombie {
+        processes.remove(pid);
+    }
+    Ok(zombie)
+}
+
+/// Ends the current process. Its children are orphaned, nobody is left to
+/// wait for the dead ones among them. The process itself stays a zombie for
+/// its parent, its kernel stack is freed once it is reaped.
+pub fn exit(status: ExitStatus) -> ! {
+    let process = get_cur_process();
+    process.kill(status);
+
+    let pid = process.pid;
+    for child in processes().iter_mut() {
+        if child.parent == Some(pid) {
+            child.parent = None;
+        }
+    }
+    processes()
+        .retain(|process| process.is_alive() || process.parent.is_some() || process.pid == pid);
+
+    schedule();
+    unreachable!("A dead process was switched to")
+}
+
+/// Timer tick, switches away from the current process once its time slice is used up
+pub fn preempt(_ctx: &mut InterruptContext) {
+    scheduler().tick(processes());
+
+    match processes().current() {
+        Some(process) => {
+            process.sched.ticks_left = process.sched.ticks_left.saturating_sub(1);
+            if process.sched.ticks_left == 0 {
+                scheduler().expired(process);
+                schedule();
+            }
+        }
+        // a sleeping process may have woken up
+        None => schedule(),
+    }
 }
 
-pub fn template_process(
-    addr: usize,
+/// Makes the process `pid` runnable again if it is sleeping
+pub fn wake(pid: Pid) {
+    if let Some(process) = processes().get(pid)
+        && process.state == State::Sleeping
+    {
+        process.state = State::Runnable;
+    }
+}
+
+/// Switches to the process the scheduler picks, or to the idle task if none
+/// can run. Returns once the caller is switched back to.
+pub fn schedule() {
+    let processes = processes();
+    let prev: *mut KernelStack = match processes.current() {
+        Some(process) => &mut process.kstack,
+        None => idle_stack(),
+    };
+    let next: *mut KernelStack = match switch_next() {
+        Some(next) => &mut next.kstack,
+        None => idle_stack(),
+    };
+    if ptr::eq(prev, next) {
+        return;
+    }
+
+    unsafe { (*prev).switch_to(&*next) };
+    finish_switch();
+}
+
+/// Leaves the boot code for the first process
+pub fn start() -> ! {
+    let mut idle = KernelStack::new().expect("Not enough memory for the idle task");
+    idle.start_kernel(idle_task);
+    unsafe { IDLE = Some(idle) };
+
+    let first = match switch_next() {
+        Some(first) => &first.kstack,
+        None => idle_stack(),
+    };
+    kernel_stack::switch_from_boot(first)
+}
+
+/// Makes the process the scheduler picks current and gives it a new time slice
+fn switch_next() -> Option<&'static mut Process> {
+    let processes = processes();
+    let next = scheduler().pick_next(processes, processes.current_pid());
+    processes.set_current(next);
+
+    let next = processes.current()?;
+    next.space.activate();
+    TSS.set_esp0(next.kstack.top() as _);
+    next.sched.ticks_left = scheduler().quantum(next);
+    Some(next)
+}
+
+fn idle_stack() -> &'static mut KernelStack {
+    unsafe {
+        (*ptr::addr_of_mut!(IDLE))
+            .as_mut()
+            .expect("The idle task is not set up")
+    }
+}
+
+/// Runs when no process can, halts until an interrupt changes that
+extern "C" fn idle_task() -> ! {
+    let mut reported = false;
+    loop {
+        finish_switch();
+
+        if !reported && processes().iter().all(|process| !process.is_alive()) {
+            info!("All processes exited");
+            reported = true;
+        }
+
+        sti();
+        hlt();
+        cli();
+        schedule();
+    }
+}
+
+/// Runs on the stack switched to. Dead orphans can be dropped now, the
+/// previous process no longer runs on its kernel stack.
+extern "C" fn finish_switch() {
+    let processes = processes();
+    let current = processes.current_pid();
+    processes.retain(|process| {
+        process.is_alive() || process.parent.is_some() || Some(process.pid) == current
+    });
+}
+
+/// Writer for the same part of the screen as `tbw`, continuing at its cursor
+pub fn shared_writer(tbw: &TextBufferWritter) -> TextBufferWritter {
+    let mut shared = TextBufferWritter::new(tbw.buffer.sub(tbw.buffer.region.clone()));
+    shared.x = tbw.x;
+    shared.y = tbw.y;
+    shared
+}
+
+/// Writer for a part of the screen, the screen is split into a grid of
+/// `width_factor` x `height_factor` cells
+pub fn template_writer(
     x: usize,
     y: usize,
     width_factor: usize,
     height_factor: usize,
-) -> Process {
+) -> TextBufferWritter {
     let kernel_tb = &TBW.borrow_mut().buffer;
 
     let split_x = kernel_tb.fb.width / width_factor;
     let split_y = kernel_tb.fb.height / height_factor;
 
-    let process_tb = kernel_tb.sub(TextBufferRegion {
+    TextBufferWritter::new(kernel_tb.sub(TextBufferRegion {
         x: x * split_x,
         y: y * split_y,
         width: split_x,
         height: split_y,
-    });
-
-    Process::new(addr as _, TextBufferWritter::new(process_tb))
+    }))
 }
 
 pub struct Process {
-    pub alive: bool,
-    pub tbw: TextBufferWritter,
-    pub ctx: InterruptContext,
-    pub pd: *mut paging::PageDirectory,
-    pub stack_pte_ind: usize,
+    pub pid: Pid,
+    pub parent: Option<Pid>,
+    pub state: State,
+    pub sched: SchedInfo,
+    pub tty: Tty,
+    pub files: FdTable,
+    pub kstack: KernelStack,
+    pub space: AddressSpace,
+    pub stack_bottom: usize,
 }
 
 impl Process {
-    pub fn new(phys_start: *mut u8, tbw: TextBufferWritter) -> Self {
-        let pd = paging::init_kernel_paging();
-        paging::init_code_pages(pd, phys_start);
-
-        let flags = EFlags::new().union(EFlags::IOPL0).union(EFlags::IF);
-
-        Self {
-            alive: true,
-            tbw,
-            ctx: InterruptContext {
-                esp: VIRT_START as _,
-                ss: USER_DS,
-                edi: 0,
-                esi: 0,
-                ebp: 0,
-                _fill: 0,
-                ebx: 0,
-                edx: 0,
-                ecx: 0,
-                eax: 0,
-                gs: USER_DS,
-                fs: USER_DS,
-                es: USER_DS,
-                ds: USER_DS,
-                vector: 0,
-                errcode: 0,
-                eip: VIRT_START as _,
-                cs: USER_CS,
-                eflags: flags,
-                cr2: 0,
-            },
-            pd,
-            stack_pte_ind: 1023,
-        }
+    pub fn new(tty: Tty) -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            pid: 0,
+            parent: None,
+            state: State::Runnable,
+            sched: SchedInfo::new(0),
+            tty,
+            files: FdTable::console(),
+            kstack: KernelStack::new()?,
+            space: AddressSpace::new()?,
+            stack_bottom: STACK_TOP,
+        })
+    }
+
+    /// Copy of the process interrupted with `ctx`, the copy returns 0 from the syscall
+    pub fn fork(&mut self, ctx: &InterruptContext) -> Result<Process, OutOfMemory> {
+        let mut child_ctx = ctx.clone();
+        child_ctx.eax = 0;
+        let mut kstack = KernelStack::new()?;
+        kstack.start_with(&child_ctx);
+
+        Ok(Process {
+            pid: 0,
+            parent: Some(self.pid),
+            state: State::Runnable,
+            sched: SchedInfo::new(self.sched.priority),
+            tty: self.tty.duplicate(),
+            files: self.files.clone(),
+            kstack,
+            space: self.space.fork()?,
+            stack_bottom: self.stack_bottom,
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        !matches!(self.state, State::Zombie(_))
     }
 
-    pub fn run(&mut self, args: &[&[u8]]) -> ! {
-        self.init(args);
-        self.jump()
+    pub fn is_runnable(&self) -> bool {
+        self.state == State::Runnable
     }
 
-    pub fn kill(&mut self) {
-        paging::disable_paging();
-        paging::delete_process_pages(self.pd);
-        self.alive = false;
+    /// Frees the memory and closes the files of the process, it stays a
+    /// zombie until it is reaped
+    fn kill(&mut self, status: ExitStatus) {
+        self.space.clear();
+        self.files.clear();
+        self.state = State::Zombie(status);
     }
 
-    pub fn respawn(&mut self) -> ! {
-        self.kill();
-        self.run(&[b"smth"]);
+    pub fn kill_oom(&mut self) -> ! {
+        writeln!(self.tty, "Out of memory").unwrap();
+        exit(ExitStatus::Killed(Fault::OutOfMemory))
     }
 
-    pub fn init(&mut self, args: &[&[u8]]) {
-        paging::disable_paging();
-        paging::init_stack_pages(self.pd);
-        let (argc, argv) = paging::init_args_pages(self.pd, args);
+    /// Loads the ELF `image` and sets up the stack with `args`
+    pub fn init(&mut self, image: &[u8], args: &[&[u8]]) -> Result<(), LoadError> {
+        self.space.clear();
+        let entry = loader::load(&mut self.space, image, STACK_TOP, ARGS_START)?;
+        self.map_stack()?;
+        let (argc, argv) = self.map_args(args)?;
 
-        self.ctx.eax = argc;
-        self.ctx.ecx = argv as _;
+        let mut ctx = user_context(entry, STACK_TOP);
+        ctx.eax = argc;
+        ctx.ecx = argv as _;
+        self.kstack.start_with(&ctx);
+        Ok(())
     }
 
-    pub fn jump(&mut self) -> ! {
-        self.alive = true;
-        paging::enable_paging(self.pd);
-        let stack_ctx = self.ctx.clone();
-        unsafe {
-            asm!("mov ebx, {}", "jmp {}", in(reg) &stack_ctx, in(reg) interrupts::pop_ctx, options(noreturn, nostack));
+    /// Maps the stack pages from the page of `addr` up to the current bottom
+    pub fn grow_stack(&mut self, addr: usize) -> Result<(), OutOfMemory> {
+        let flags = PageFlags::WRITABLE | PageFlags::USER;
+
+        while self.stack_bottom > addr {
+            self.space
+                .map_zeroed(self.stack_bottom - PAGE_SIZE, flags)?;
+            self.stack_bottom -= PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    fn map_stack(&mut self) -> Result<(), OutOfMemory> {
+        self.stack_bottom = STACK_TOP;
+        self.grow_stack(STACK_TOP - PAGE_SIZE)?;
+        self.space.add_region(Region {
+            start: STACK_LIMIT,
+            end: STACK_TOP,
+            kind: RegionKind::Stack,
+        });
+        Ok(())
+    }
+
+    /// argv page followed by one page per argument
+    fn map_args(&mut self, args: &[&[u8]]) -> Result<(u32, *const *const u8), OutOfMemory> {
+        debug_assert!(args.len() <= MAX_ARGS);
+        let flags = PageFlags::WRITABLE | PageFlags::USER;
+
+        let argv_page = self.space.map_zeroed(ARGS_START, flags)?;
+        let argv = argv_page.as_ptr() as *mut usize;
+        for (i, arg) in args.iter().enumerate() {
+            debug_assert!(arg.len() <= PAGE_SIZE);
+            let virt = ARGS_START + (i + 1) * PAGE_SIZE;
+            let page = self.space.map_zeroed(virt, flags)?;
+
+            unsafe {
+                (page.as_ptr() as *mut u8).copy_from(arg.as_ptr(), arg.len());
+                *argv.add(i) = virt;
+            }
         }
+
+        self.space.add_region(Region {
+            start: ARGS_START,
+            end: ARGS_START + (args.len() + 1) * PAGE_SIZE,
+            kind: RegionKind::Args,
+        });
+        Ok((args.len() as _, ARGS_START as _))
+    }
+}
+
+/// Registers a process starts in user space with
+fn user_context(eip: usize, esp: usize) -> InterruptContext {
+    InterruptContext {
+        esp: esp as _,
+        ss: USER_DS,
+        edi: 0,
+        esi: 0,
+        ebp: 0,
+        _fill: 0,
+        ebx: 0,
+        edx: 0,
+        ecx: 0,
+        eax: 0,
+        gs: USER_DS,
+        fs: USER_DS,
+        es: USER_DS,
+        ds: USER_DS,
+        vector: 0,
+        errcode: 0,
+        eip: eip as _,
+        cs: USER_CS,
+        eflags: EFlags::new().union(EFlags::IOPL0).union(EFlags::IF),
+        cr2: 0,
     }
 }
@@ -0,0 +1,172 @@
+use alloc::boxed::Box;
+use core::ptr;
+
+use super::{Pid, Process, ProcessTable, State, get_cur_process, schedule, wake};
+use crate::time::{self, Deadline};
+
+/// Nice value, lower runs first
+pub type Priority = i8;
+pub const MIN_PRIORITY: Priority = 19;
+pub const MAX_PRIORITY: Priority = -20;
+
+static mut SCHEDULER: Option<Box<dyn Scheduler>> = None;
+
+/// Per process bookkeeping of the scheduler
+#[derive(Debug, Clone, Copy)]
+pub struct SchedInfo {
+    pub priority: Priority,
+    /// How many queues below the one of its priority the process is
+    pub level: u8,
+    /// Ticks until the process is preempted
+    pub ticks_left: u32,
+}
+
+impl SchedInfo {
+    pub const fn new(priority: Priority) -> Self {
+        Self {
+            priority,
+            level: 0,
+            ticks_left: 0,
+        }
+    }
+}
+
+/// Policy deciding which process runs next and for how long
+pub trait Scheduler {
+    fn name(&self) -> &'static str;
+
+    /// Next process among the runnable ones, `current` ran last
+    fn pick_next(&mut self, processes: &ProcessTable, current: Option<Pid>) -> Option<Pid>;
+
+    /// Time slice in ticks `process` gets when it is switched to
+    fn quantum(&self, process: &Process) -> u32;
+
+    /// `process` ran for its whole time slice
+    fn expired(&mut self, _process: &mut Process) {}
+
+    /// Called on every tick before the running process is accounted
+    fn tick(&mut self, _processes: &mut ProcessTable) {}
+}
+
+/// Every runnable process in pid order, all with the same time slice
+pub struct RoundRobin {
+    quantum: u32,
+}
+
+impl RoundRobin {
+    pub const fn new(quantum: u32) -> Self {
+        Self { quantum }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round robin"
+    }
+
+    fn pick_next(&mut self, processes: &ProcessTable, current: Option<Pid>) -> Option<Pid> {
+        round_robin(processes.iter().filter(|p| p.is_runnable()), current)
+    }
+
+    fn quantum(&self, _process: &Process) -> u32 {
+        self.quantum
+    }
+}
+
+/// Multilevel feedback queue. The priority picks the queue a process starts
+/// in, every time slice it uses up moves it one queue down. Processes that
+/// sleep before their slice ends, like the interactive ones, stay on top.
+/// All of them go back up every `boost_interval` ticks, so nothing starves.
+pub struct Mlfq<const LEVELS: usize> {
+    quanta: [u32; LEVELS],
+    boost_interval: u32,
+    since_boost: u32,
+}
+
+impl<const LEVELS: usize> Mlfq<LEVELS> {
+    /// `quanta` are the time slices of the queues, from the highest one
+    pub const fn new(quanta: [u32; LEVELS], boost_interval: u32) -> Self {
+        Self {
+            quanta,
+            boost_interval,
+            since_boost: 0,
+        }
+    }
+
+    /// Priorities are spread over the upper half of the queues
+    fn level(&self, process: &Process) -> usize {
+        let range = (MIN_PRIORITY - MAX_PRIORITY) as usize + 1;
+        let base = (process.sched.priority - MAX_PRIORITY) as usize * LEVELS.div_ceil(2) / range;
+        (base + process.sched.level as usize).min(LEVELS - 1)
+    }
+}
+
+impl<const LEVELS: usize> Scheduler for Mlfq<LEVELS> {
+    fn name(&self) -> &'static str {
+        "multilevel feedback queue"
+    }
+
+    fn pick_next(&mut self, processes: &ProcessTable, current: Option<Pid>) -> Option<Pid> {
+        let runnable = processes.iter().filter(|p| p.is_runnable());
+        let top = runnable.clone().map(|p| self.level(p)).min()?;
+        round_robin(runnable.filter(|p| self.level(p) == top), current)
+    }
+
+    fn quantum(&self, process: &Process) -> u32 {
+        self.quanta[self.level(process)]
+    }
+
+    fn expired(&mut self, process: &mut Process) {
+        if self.level(process) < LEVELS - 1 {
+            process.sched.level += 1;
+        }
+    }
+
+    fn tick(&mut self, processes: &mut ProcessTable) {
+        self.since_boost += 1;
+        if self.since_boost >= self.boost_interval {
+            self.since_boost = 0;
+            for process in processes.iter_mut() {
+                process.sched.level = 0;
+            }
+        }
+    }
+}
+
+/// First of `candidates` after `current` in pid order, wrapping around
+fn round_robin<'a>(
+    mut candidates: impl Iterator<Item = &'a Process> + Clone,
+    current: Option<Pid>,
+) -> Option<Pid> {
+    let after = current.unwrap_or(0);
+    candidates
+        .clone()
+        .find(|process| process.pid > after)
+        .or_else(|| candidates.next())
+        .map(|process| process.pid)
+}
+
+pub fn set_scheduler(scheduler: Box<dyn Scheduler>) {
+    unsafe { SCHEDULER = Some(scheduler) };
+}
+
+pub fn scheduler() -> &'static mut dyn Scheduler {
+    unsafe {
+        (*ptr::addr_of_mut!(SCHEDULER))
+            .as_deref_mut()
+            .expect("No scheduler is set")
+    }
+}
+
+/// Puts the current process to sleep until `deadline`
+pub fn sleep_until(deadline: Deadline) {
+    if deadline.is_expired() {
+        return;
+    }
+
+    let process = get_cur_process();
+    process.state = State::Sleeping;
+    let pid = process.pid;
+    time::add_timer(deadline, move || wake(pid));
+    schedule();
+}
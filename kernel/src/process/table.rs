@@ -0,0 +1,76 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use super::Process;
+
+pub type Pid = u32;
+
+/// Processes ordered by pid. They are boxed, so references to them stay
+/// valid while the table grows.
+pub struct ProcessTable {
+    #[allow(clippy::vec_box)]
+    processes: Vec<Box<Process>>,
+    current: Option<Pid>,
+    next_pid: Pid,
+}
+
+impl ProcessTable {
+    pub const fn new() -> Self {
+        Self {
+            processes: Vec::new(),
+            current: None,
+            next_pid: 1,
+        }
+    }
+
+    pub fn insert(&mut self, mut process: Process) -> Pid {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+
+        process.pid = pid;
+        self.processes.push(Box::new(process));
+        pid
+    }
+
+    pub fn get(&mut self, pid: Pid) -> Option<&mut Process> {
+        let index = self.index(pid)?;
+        Some(&mut self.processes[index])
+    }
+
+    pub fn remove(&mut self, pid: Pid) -> Option<Box<Process>> {
+        let index = self.index(pid)?;
+        Some(self.processes.remove(index))
+    }
+
+    /// Drops the processes `f` returns false for
+    pub fn retain(&mut self, mut f: impl FnMut(&Process) -> bool) {
+        self.processes.retain(|process| f(process));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Process> + Clone {
+        self.processes.iter().map(|process| &**process)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.processes.iter_mut().map(|process| &mut **process)
+    }
+
+    pub fn current_pid(&self) -> Option<Pid> {
+        self.current
+    }
+
+    pub fn current(&mut self) -> Option<&mut Process> {
+        self.get(self.current?)
+    }
+
+    /// `pid` must be in the table
+    pub fn set_current(&mut self, pid: Option<Pid>) {
+        debug_assert!(pid.is_none_or(|pid| self.index(pid).is_some()));
+        self.current = pid;
+    }
+
+    fn index(&self, pid: Pid) -> Option<usize> {
+        self.processes
+            .binary_search_by_key(&pid, |process| process.pid)
+            .ok()
+    }
+}
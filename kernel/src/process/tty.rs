@@ -0,0 +1,63 @@
+use utils::{
+    io::{self, Write},
+    textbuffer::TextBufferWritter,
+};
+
+use super::shared_writer;
+use crate::{device_manager::DEVICES, drivers::uart::Uart};
+
+/// Terminal of a process, where its input comes from and its output goes
+pub enum Tty {
+    /// Part of the screen, the input is key codes from the PS/2 keyboard
+    Screen(TextBufferWritter),
+    /// Serial port, the input is the bytes received
+    Serial(&'static Uart),
+}
+
+impl Tty {
+    /// Another handle to the same terminal, screen output continues at the cursor
+    pub fn duplicate(&self) -> Tty {
+        match self {
+            Self::Screen(tbw) => Self::Screen(shared_writer(tbw)),
+            Self::Serial(uart) => Self::Serial(uart),
+        }
+    }
+
+    /// Next input, sleeps until there is some if `wait` is set and returns 0 otherwise
+    pub fn read(&self, wait: bool) -> u8 {
+        match (self, wait) {
+            (Self::Screen(_), true) => DEVICES.ps2keyboard.read_wait(),
+            (Self::Screen(_), false) => DEVICES.ps2keyboard.read(),
+            (Self::Serial(uart), true) => uart.read_wait(),
+            (Self::Serial(uart), false) => uart.read().unwrap_or(0),
+        }
+    }
+}
+
+impl Write for Tty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Screen(tbw) => tbw.write(buf),
+            Self::Serial(uart) => {
+                // serial terminals move to the next line and back separately
+                for line in buf.split_inclusive(|&byte| byte == b'\n') {
+                    match line.strip_suffix(b"\n") {
+                        Some(line) => {
+                            Uart::write(uart, line);
+                            Uart::write(uart, b"\r\n");
+                        }
+                        None => Uart::write(uart, line),
+                    }
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Screen(tbw) => tbw.flush(),
+            Self::Serial(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use alloc::collections::VecDeque;
+use utils::nullsync;
+
+use super::{Pid, State, get_cur_process, schedule, wake};
+
+/// Processes sleeping until an event, the event's interrupt handler or
+/// syscall wakes them up. Sleeping and checking for the event must happen
+/// with interrupts disabled, or the wake up can be lost.
+pub struct WaitQueue {
+    sleepers: nullsync::RefCell<VecDeque<Pid>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            sleepers: nullsync::RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Takes the current process off the run queue until it is woken up
+    pub fn sleep(&self) {
+        let process = get_cur_process();
+        process.state = State::Sleeping;
+        self.sleepers.borrow_mut().push_back(process.pid);
+        schedule();
+    }
+
+    pub fn wake_all(&self) {
+        while let Some(pid) = self.sleepers.borrow_mut().pop_front() {
+            wake(pid);
+        }
+    }
+}
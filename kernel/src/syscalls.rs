@@ -1,61 +1,663 @@
-use core::slice;
+use alloc::{string::String, vec::Vec};
+use core::{mem, slice, str};
 
 use utils::io::Write;
 
 use crate::{
-    device_manager::DEVICES, interrupts::InterruptContext, process::get_cur_process, x86_utils::sti,
+    boot_info::boot_info,
+    device_manager::DEVICES,
+    fs::{self, FileType, FsError, OpenFlags, SeekFrom, Stat},
+    interrupts::InterruptContext,
+    paging::{KERNEL_BASE, PAGE_SIZE, PageFlags},
+    process::{
+        self, ExitStatus, LoadError, MAX_ARGS, NoChild, Pid, SpawnError, Tty, get_cur_process,
+        scheduler::{self, MAX_PRIORITY, MIN_PRIORITY},
+    },
+    time::{self, Deadline, NANOS_PER_SEC},
 };
 
 const INVALID_ARGS: i32 = -1;
 const UNKNOWN_SYSCALL: i32 = -2;
-const WRITE_ERROR: i32 = -3;
+const IO_ERROR: i32 = -3;
+const NOT_FOUND: i32 = -4;
+const OUT_OF_MEMORY: i32 = -5;
+const BAD_EXECUTABLE: i32 = -6;
+const NO_CHILD: i32 = -7;
+const BAD_FD: i32 = -8;
+const NOT_A_DIRECTORY: i32 = -9;
+const IS_A_DIRECTORY: i32 = -10;
+const EXISTS: i32 = -11;
+const READ_ONLY: i32 = -12;
+const NOT_SUPPORTED: i32 = -13;
+const TOO_MANY_FILES: i32 = -14;
+const NO_SPACE: i32 = -15;
+const NOT_EMPTY: i32 = -16;
+const SYMLINK_LOOP: i32 = -17;
+const CROSS_DEVICE: i32 = -18;
+
+/// unlinkat flag, remove a directory like rmdir
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// lseek origins
+const SEEK_SET: u32 = 0;
+const SEEK_CUR: u32 = 1;
+const SEEK_END: u32 = 2;
+
+/// File type bits of `FileStat::mode`
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// File types of the getdents records
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+/// waitpid option, return 0 instead of waiting for a child to exit
+const WNOHANG: u32 = 1;
+/// setpriority target, the only one there is
+const PRIO_PROCESS: u32 = 0;
+
+/// clock_gettime clocks, the realtime one counts from the unix epoch and the
+/// others from boot
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
+const CLOCK_BOOTTIME: u32 = 7;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Timespec {
+    sec: i32,
+    nsec: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Timeval {
+    sec: i32,
+    usec: i32,
+}
+
+/// What stat and fstat store
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FileStat {
+    ino: u32,
+    /// File type and permission bits
+    mode: u32,
+    nlink: u32,
+    size: u32,
+    mtime: u32,
+}
+
+impl From<Stat> for FileStat {
+    fn from(stat: Stat) -> Self {
+        let kind = match stat.kind {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::CharDevice => S_IFCHR,
+            FileType::Symlink => S_IFLNK,
+        };
+        Self {
+            ino: stat.ino as _,
+            mode: kind | stat.mode as u32,
+            nlink: stat.nlink,
+            size: stat.size.min(u32::MAX as u64) as _,
+            mtime: stat.mtime as _,
+        }
+    }
+}
+
+impl Timespec {
+    fn from_nanos(nanos: u64) -> Self {
+        Self {
+            sec: (nanos / NANOS_PER_SEC) as _,
+            nsec: (nanos % NANOS_PER_SEC) as _,
+        }
+    }
+
+    fn nanos(self) -> u64 {
+        self.sec as u64 * NANOS_PER_SEC + self.nsec as u64
+    }
+}
 
 pub fn generic_handler(ctx: &mut InterruptContext) {
     ctx.eax = match ctx.eax {
         1 => exit(ctx.ebx),
-        3 => read(),
-        4 => {
-            if ctx.ebx == 0 {
-                INVALID_ARGS
-            } else {
-                let buf = unsafe { slice::from_raw_parts(ctx.ebx as _, ctx.ecx as _) };
-                write(buf)
-            }
-        }
+        2 => fork(ctx),
+        3 => read(ctx.ebx, ctx.ecx, ctx.edx),
+        4 => write(ctx.ebx, ctx.ecx, ctx.edx),
+        5 => open(ctx.ebx, ctx.ecx),
+        6 => close(ctx.ebx),
+        7 => waitpid(ctx.ebx as _, ctx.ecx, ctx.edx),
+        9 => link(ctx.ebx, ctx.ecx),
         10 => get_fb_addr(),
         11 => get_fb_width(),
         12 => get_fb_height(),
+        13 => time(ctx.ebx),
+        14 => uptime(),
+        15 => set_tty(ctx.ebx),
+        19 => lseek(ctx.ebx, ctx.ecx as _, ctx.edx),
+        38 => rename(ctx.ebx, ctx.ecx),
+        39 => mkdir(ctx.ebx),
+        40 => rmdir(ctx.ebx),
+        78 => gettimeofday(ctx.ebx, ctx.ecx),
+        92 => truncate(ctx.ebx, ctx.ecx),
+        93 => ftruncate(ctx.ebx, ctx.ecx),
+        97 => setpriority(ctx.ebx, ctx.ecx, ctx.edx as _),
+        106 => stat(ctx.ebx, ctx.ecx),
+        108 => fstat(ctx.ebx, ctx.ecx),
+        141 => getdents(ctx.ebx, ctx.ecx, ctx.edx),
+        158 => sched_yield(),
+        162 => nanosleep(ctx.ebx, ctx.ecx),
+        120 => spawn(ctx.ebx, ctx.ecx, ctx.edx),
+        265 => clock_gettime(ctx.ebx, ctx.ecx),
+        301 => unlinkat(ctx.ebx, ctx.ecx, ctx.edx),
         _ => UNKNOWN_SYSCALL,
     } as _
 }
 
-fn read() -> i32 {
-    DEVICES.ps2keyboard.read() as i32
+/// Reads up to `count` bytes from `fd` into `buf`, returns how many were read
+fn read(fd: u32, buf: u32, count: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd).cloned() else {
+        return BAD_FD;
+    };
+    let Some(buf) = user_slice_mut::<u8>(buf, count) else {
+        return INVALID_ARGS;
+    };
+
+    match file.read(buf) {
+        Ok(count) => count as _,
+        Err(err) => fs_error(err),
+    }
+}
+
+fn write(fd: u32, buf: u32, count: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd).cloned() else {
+        return BAD_FD;
+    };
+    let Some(buf) = user_slice::<u8>(buf, count) else {
+        return INVALID_ARGS;
+    };
+
+    match file.write(buf) {
+        Ok(count) => count as _,
+        Err(err) => fs_error(err),
+    }
+}
+
+/// Opens the file at the absolute `path`, returns the lowest free descriptor
+fn open(path: u32, flags: u32) -> i32 {
+    let Some(path) = user_path(path) else {
+        return INVALID_ARGS;
+    };
+    let Some(flags) = OpenFlags::from_bits(flags) else {
+        return INVALID_ARGS;
+    };
+
+    let file = match fs::open(path, flags) {
+        Ok(file) => file,
+        Err(err) => return fs_error(err),
+    };
+    match get_cur_process().files.insert(file) {
+        Some(fd) => fd as _,
+        None => TOO_MANY_FILES,
+    }
+}
+
+fn close(fd: u32) -> i32 {
+    match get_cur_process().files.close(fd) {
+        Some(_) => 0,
+        None => BAD_FD,
+    }
+}
+
+/// Moves the offset of `fd`, returns the new one
+fn lseek(fd: u32, offset: i32, whence: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd) else {
+        return BAD_FD;
+    };
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as _),
+        SEEK_CUR => SeekFrom::Current(offset as _),
+        SEEK_END => SeekFrom::End(offset as _),
+        _ => return INVALID_ARGS,
+    };
+
+    match file.seek(pos) {
+        Ok(offset) => i32::try_from(offset).unwrap_or(INVALID_ARGS),
+        Err(err) => fs_error(err),
+    }
+}
+
+fn stat(path: u32, buf: u32) -> i32 {
+    let Some(path) = user_path(path) else {
+        return INVALID_ARGS;
+    };
+    let Some([buf]) = user_slice_mut::<FileStat>(buf, 1) else {
+        return INVALID_ARGS;
+    };
+
+    match fs::lookup(path) {
+        Ok(inode) => {
+            *buf = inode.stat().into();
+            0
+        }
+        Err(err) => fs_error(err),
+    }
+}
+
+/// Creates a directory. The mode is ignored, nothing checks permissions.
+fn mkdir(path: u32) -> i32 {
+    match user_path(path).map(fs::mkdir) {
+        Some(Ok(())) => 0,
+        Some(Err(err)) => fs_error(err),
+        None => INVALID_ARGS,
+    }
+}
+
+fn rmdir(path: u32) -> i32 {
+    match user_path(path).map(fs::rmdir) {
+        Some(Ok(())) => 0,
+        Some(Err(err)) => fs_error(err),
+        None => INVALID_ARGS,
+    }
+}
+
+/// Removes the file at `path`, or the directory with `AT_REMOVEDIR`. Paths
+/// are absolute, `dirfd` is ignored. This is the only unlink, Linux has
+/// plain unlink at 10 where the framebuffer calls are.
+fn unlinkat(_dirfd: u32, path: u32, flags: u32) -> i32 {
+    let Some(path) = user_path(path) else {
+        return INVALID_ARGS;
+    };
+    let result = match flags {
+        0 => fs::unlink(path),
+        AT_REMOVEDIR => fs::rmdir(path),
+        _ => return INVALID_ARGS,
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => fs_error(err),
+    }
+}
+
+/// Adds the name `new` for the file at `old`, both on the same file system
+fn link(old: u32, new: u32) -> i32 {
+    let (Some(old), Some(new)) = (user_path(old), user_path(new)) else {
+        return INVALID_ARGS;
+    };
+    match fs::link(old, new) {
+        Ok(()) => 0,
+        Err(err) => fs_error(err),
+    }
+}
+
+/// Moves the file at `old` to `new`, replacing a file or empty directory there
+fn rename(old: u32, new: u32) -> i32 {
+    let (Some(old), Some(new)) = (user_path(old), user_path(new)) else {
+        return INVALID_ARGS;
+    };
+    match fs::rename(old, new) {
+        Ok(()) => 0,
+        Err(err) => fs_error(err),
+    }
+}
+
+fn truncate(path: u32, length: u32) -> i32 {
+    let Some(path) = user_path(path) else {
+        return INVALID_ARGS;
+    };
+    match fs::open(path, OpenFlags::WRONLY).and_then(|file| file.truncate(length as _)) {
+        Ok(()) => 0,
+        Err(err) => fs_error(err),
+    }
+}
+
+fn ftruncate(fd: u32, length: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd).cloned() else {
+        return BAD_FD;
+    };
+    match file.truncate(length as _) {
+        Ok(()) => 0,
+        Err(err) => fs_error(err),
+    }
+}
+
+fn fstat(fd: u32, buf: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd) else {
+        return BAD_FD;
+    };
+    let Some([buf]) = user_slice_mut::<FileStat>(buf, 1) else {
+        return INVALID_ARGS;
+    };
+
+    *buf = file.stat().into();
+    0
+}
+
+/// Fills `buf` with `linux_dirent` records for the entries of the directory
+/// `fd`, returns the bytes used, 0 after the last entry
+fn getdents(fd: u32, buf: u32, count: u32) -> i32 {
+    let Some(file) = get_cur_process().files.get(fd).cloned() else {
+        return BAD_FD;
+    };
+    let Some(buf) = user_slice_mut::<u8>(buf, count) else {
+        return INVALID_ARGS;
+    };
+
+    let mut used = 0;
+    let mut full = false;
+    let result = file.read_dir(|entry, next| {
+        // inode, offset of the next record, record length, the NUL
+        // terminated name and the type in the last byte
+        let len = (10 + entry.name.len() + 2).next_multiple_of(4);
+        let Some(record) = buf.get_mut(used..used + len) else {
+            full = true;
+            return false;
+        };
+        record.fill(0);
+        record[..4].copy_from_slice(&(entry.ino as u32).to_le_bytes());
+        record[4..8].copy_from_slice(&(next as u32).to_le_bytes());
+        record[8..10].copy_from_slice(&(len as u16).to_le_bytes());
+        record[10..10 + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        record[len - 1] = match entry.kind {
+            FileType::Regular => DT_REG,
+            FileType::Directory => DT_DIR,
+            FileType::CharDevice => DT_CHR,
+            FileType::Symlink => DT_LNK,
+        };
+        used += len;
+        true
+    });
+
+    match result {
+        // not even one entry fits
+        Ok(()) if used == 0 && full => INVALID_ARGS,
+        Ok(()) => used as _,
+        Err(err) => fs_error(err),
+    }
+}
+
+/// Makes the serial port `port`, 1 for COM1 or 2 for COM2, the terminal of
+/// the process. Children started afterwards inherit it.
+fn set_tty(port: u32) -> i32 {
+    let uart = match port {
+        1 => &DEVICES.com1,
+        2 => &DEVICES.com2,
+        _ => return INVALID_ARGS,
+    };
+    if !uart.is_present() {
+        return NOT_FOUND;
+    }
+
+    get_cur_process().tty = Tty::Serial(uart);
+    0
 }
 
 fn exit(code: u32) -> ! {
     let process = get_cur_process();
-    writeln!(process.tbw, "EXIT WITH CODE {}", code).unwrap();
-    process.kill();
-    sti();
-    loop {}
+    writeln!(process.tty, "EXIT WITH CODE {}", code).unwrap();
+    process::exit(ExitStatus::Exited(code))
 }
 
-fn write(buf: &[u8]) -> i32 {
-    match get_cur_process().tbw.write(buf) {
-        Ok(count) => count as _,
-        Err(_) => WRITE_ERROR,
+/// Returns the pid of the child to the parent and 0 to the child
+fn fork(ctx: &InterruptContext) -> i32 {
+    match get_cur_process().fork(ctx) {
+        Ok(child) => process::processes().insert(child) as _,
+        Err(_) => OUT_OF_MEMORY,
+    }
+}
+
+/// Reaps the child `pid`, or any child if it is -1, and stores its wait status
+/// at `status` unless that is null
+fn waitpid(pid: i32, status: u32, options: u32) -> i32 {
+    let pid = match pid {
+        -1 => None,
+        1.. => Some(pid as Pid),
+        _ => return INVALID_ARGS,
+    };
+    let status = match status {
+        0 => None,
+        addr => match user_slice_mut::<u32>(addr, 1) {
+            Some(status) => Some(&mut status[0]),
+            None => return INVALID_ARGS,
+        },
+    };
+
+    loop {
+        match process::wait(get_cur_process().pid, pid) {
+            Ok(Some((pid, exit_status))) => {
+                if let Some(status) = status {
+                    *status = exit_status.encode();
+                }
+                return pid as _;
+            }
+            Ok(None) if options & WNOHANG != 0 => return 0,
+            Ok(None) => process::CHILD_EXITED.sleep(),
+            Err(NoChild) => return NO_CHILD,
+        }
+    }
+}
+
+/// Starts the program at the absolute path `name` or the boot module `name`,
+/// `argv` points to `argc` NUL terminated strings.
+/// The new process writes to the same part of the screen as its parent.
+fn spawn(name: u32, argv: u32, argc: u32) -> i32 {
+    let Some(name) = user_cstr(name, PAGE_SIZE) else {
+        return INVALID_ARGS;
+    };
+    if argc as usize > MAX_ARGS {
+        return INVALID_ARGS;
+    }
+    let Some(argv) = user_slice::<u32>(argv, argc) else {
+        return INVALID_ARGS;
+    };
+
+    let mut args = Vec::new();
+    if args.try_reserve(argv.len()).is_err() {
+        return OUT_OF_MEMORY;
+    }
+    for &arg in argv {
+        // the arguments get a page each, with room for the terminating NUL
+        match user_cstr(arg, PAGE_SIZE - 1) {
+            Some(arg) => args.push(arg),
+            None => return INVALID_ARGS,
+        }
+    }
+
+    let parent = get_cur_process();
+    match process::spawn(name, &args, parent.tty.duplicate(), Some(parent.pid)) {
+        Ok(pid) => pid as _,
+        Err(SpawnError::NotFound) => NOT_FOUND,
+        Err(SpawnError::Read(err)) => fs_error(err),
+        Err(SpawnError::Load(LoadError::OutOfMemory)) => OUT_OF_MEMORY,
+        Err(SpawnError::Load(LoadError::BadElf(err))) => {
+            crate::info!("{}: {:?}", String::from_utf8_lossy(name), err);
+            BAD_EXECUTABLE
+        }
+        Err(SpawnError::Load(LoadError::BadLayout)) => BAD_EXECUTABLE,
+    }
+}
+
+/// Lets the next process run, the caller stays runnable
+fn sched_yield() -> i32 {
+    process::schedule();
+    0
+}
+
+/// Sleeps for the duration at `req`. `rem` is left alone, sleeps are never cut short.
+fn nanosleep(req: u32, _rem: u32) -> i32 {
+    let Some(&[req]) = user_slice::<Timespec>(req, 1) else {
+        return INVALID_ARGS;
+    };
+    if req.sec < 0 || !(0..NANOS_PER_SEC as i32).contains(&req.nsec) {
+        return INVALID_ARGS;
+    }
+
+    scheduler::sleep_until(Deadline::after(req.nanos()));
+    0
+}
+
+fn clock_gettime(clock: u32, tp: u32) -> i32 {
+    let now = match clock {
+        CLOCK_REALTIME => time::realtime(),
+        CLOCK_MONOTONIC | CLOCK_BOOTTIME => time::now(),
+        _ => return INVALID_ARGS,
+    };
+    let Some([tp]) = user_slice_mut::<Timespec>(tp, 1) else {
+        return INVALID_ARGS;
+    };
+
+    *tp = Timespec::from_nanos(now);
+    0
+}
+
+/// Seconds since the unix epoch, also stored at `tloc` unless that is null
+fn time(tloc: u32) -> i32 {
+    let secs = (time::realtime() / NANOS_PER_SEC) as i32;
+    if tloc != 0 {
+        match user_slice_mut::<i32>(tloc, 1) {
+            Some([tloc]) => *tloc = secs,
+            _ => return INVALID_ARGS,
+        }
+    }
+    secs
+}
+
+/// Stores the wall clock time at `tv`. Time zones are not supported, `tz`
+/// must be null.
+fn gettimeofday(tv: u32, tz: u32) -> i32 {
+    if tz != 0 {
+        return INVALID_ARGS;
+    }
+    let Some([tv]) = user_slice_mut::<Timeval>(tv, 1) else {
+        return INVALID_ARGS;
+    };
+
+    let now = time::realtime();
+    *tv = Timeval {
+        sec: (now / NANOS_PER_SEC) as _,
+        usec: (now % NANOS_PER_SEC / 1000) as _,
+    };
+    0
+}
+
+/// Whole seconds since boot
+fn uptime() -> i32 {
+    (time::now() / NANOS_PER_SEC) as _
+}
+
+/// Sets the nice value of the process `who`, 0 is the caller. Values outside
+/// of -20..=19 are clamped.
+fn setpriority(which: u32, who: u32, prio: i32) -> i32 {
+    if which != PRIO_PROCESS {
+        return INVALID_ARGS;
+    }
+    let process = match who {
+        0 => get_cur_process(),
+        pid => match process::processes().get(pid) {
+            Some(process) if process.is_alive() => process,
+            _ => return NOT_FOUND,
+        },
+    };
+
+    process.sched.priority = prio.clamp(MAX_PRIORITY as _, MIN_PRIORITY as _) as _;
+    0
+}
+
+/// `len` values at the user address `addr`, if all of them are mapped user memory
+fn user_slice<T>(addr: u32, len: u32) -> Option<&'static [T]> {
+    let start = addr as usize;
+    let end = start.checked_add((len as usize).checked_mul(mem::size_of::<T>())?)?;
+    if start == 0 || end > KERNEL_BASE || !start.is_multiple_of(mem::align_of::<T>()) {
+        return None;
+    }
+
+    let space = &get_cur_process().space;
+    let mapped = (start & !(PAGE_SIZE - 1)..end)
+        .step_by(PAGE_SIZE)
+        .all(|page| {
+            space
+                .entry(page)
+                .is_some_and(|pte| pte.flags().contains(PageFlags::USER))
+        });
+    mapped.then(|| unsafe { slice::from_raw_parts(start as _, len as _) })
+}
+
+/// Like `user_slice`, but the memory must be writable. Copy on write pages are
+/// copied first, the kernel ignores the read only bit.
+fn user_slice_mut<T>(addr: u32, len: u32) -> Option<&'static mut [T]> {
+    let start = addr as usize;
+    let end = start.checked_add((len as usize).checked_mul(mem::size_of::<T>())?)?;
+    if start == 0 || end > KERNEL_BASE || !start.is_multiple_of(mem::align_of::<T>()) {
+        return None;
+    }
+
+    let space = &mut get_cur_process().space;
+    for page in (start & !(PAGE_SIZE - 1)..end).step_by(PAGE_SIZE) {
+        space.copy_on_write(page).ok()?;
+        let writable = space
+            .entry(page)
+            .is_some_and(|pte| pte.flags().contains(PageFlags::USER | PageFlags::WRITABLE));
+        if !writable {
+            return None;
+        }
+    }
+    Some(unsafe { slice::from_raw_parts_mut(start as _, len as _) })
+}
+
+/// NUL terminated string at the user address `addr`, without the NUL
+fn user_cstr(addr: u32, max_len: usize) -> Option<&'static [u8]> {
+    let start = addr as usize;
+    let mut chunk_start = start;
+
+    while chunk_start - start <= max_len {
+        let chunk_end = (chunk_start + 1).next_multiple_of(PAGE_SIZE);
+        let chunk = user_slice::<u8>(chunk_start as _, (chunk_end - chunk_start) as _)?;
+
+        if let Some(nul) = chunk.iter().position(|&byte| byte == 0) {
+            let len = chunk_start + nul - start;
+            return (len <= max_len).then(|| unsafe { slice::from_raw_parts(start as _, len) });
+        }
+        chunk_start = chunk_end;
+    }
+    None
+}
+
+/// Path at the user address `addr`, it must be UTF-8
+fn user_path(addr: u32) -> Option<&'static str> {
+    str::from_utf8(user_cstr(addr, PAGE_SIZE)?).ok()
+}
+
+fn fs_error(err: FsError) -> i32 {
+    match err {
+        FsError::NotFound => NOT_FOUND,
+        FsError::NotADirectory => NOT_A_DIRECTORY,
+        FsError::IsADirectory => IS_A_DIRECTORY,
+        FsError::Exists => EXISTS,
+        FsError::ReadOnly => READ_ONLY,
+        FsError::NoSpace => NO_SPACE,
+        FsError::NotEmpty => NOT_EMPTY,
+        FsError::InvalidPath | FsError::InvalidArgs => INVALID_ARGS,
+        FsError::BadFd => BAD_FD,
+        FsError::Unsupported => NOT_SUPPORTED,
+        FsError::Io => IO_ERROR,
+        FsError::SymlinkLoop => SYMLINK_LOOP,
+        FsError::CrossDevice => CROSS_DEVICE,
     }
 }
 
 fn get_fb_addr() -> i32 {
-    unsafe { crate::framebuffer_addr as i32 }
+    boot_info().framebuffer.addr as i32
 }
 
 fn get_fb_width() -> i32 {
-    unsafe { crate::framebuffer_width as i32 }
+    boot_info().framebuffer.width as i32
 }
 
 fn get_fb_height() -> i32 {
-    unsafe { crate::framebuffer_height as i32 }
+    boot_info().framebuffer.height as i32
 }
@@ -0,0 +1,145 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use utils::nullsync;
+
+use crate::{
+    device_manager::DEVICES,
+    drivers::pit::{self, MAX_FREQ},
+    interrupts::InterruptContext,
+    process,
+    x86_utils::rdtsc,
+};
+
+/// Frequency of the PIT interrupt, the tick of the clock and the scheduler
+pub const TICK_HZ: u32 = 10_000;
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// PIT periods the TSC is measured over, about 50 ms
+const CALIBRATION_COUNT: u16 = (MAX_FREQ / 20) as u16;
+
+/// PIT interrupts since the clock started
+static mut TICKS: u64 = 0;
+/// TSC at the last tick
+static mut TICK_TSC: u64 = 0;
+/// TSC frequency measured against the PIT
+static mut TSC_HZ: u64 = 0;
+
+/// Nanoseconds since the unix epoch when the monotonic clock was at 0
+static mut REALTIME_OFFSET: u64 = 0;
+
+/// Callbacks by deadline, the second key orders the ones with the same deadline
+type Timers = BTreeMap<(u64, u64), Box<dyn FnOnce()>>;
+
+static TIMERS: nullsync::RefCell<Timers> = nullsync::RefCell::new(BTreeMap::new());
+static mut NEXT_TIMER: u64 = 0;
+
+/// Point on the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(u64);
+
+impl Deadline {
+    pub fn after(nanos: u64) -> Self {
+        Self(now().saturating_add(nanos))
+    }
+
+    pub fn nanos(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_expired(self) -> bool {
+        now() >= self.0
+    }
+}
+
+/// Measures the TSC against the PIT, the PIT must be set up
+pub fn init() {
+    let start = rdtsc();
+    DEVICES.pit.wait(CALIBRATION_COUNT);
+    let end = rdtsc();
+
+    unsafe {
+        TSC_HZ = (end - start) * MAX_FREQ as u64 / CALIBRATION_COUNT as u64;
+        TICK_TSC = end;
+    }
+}
+
+pub fn tsc_hz() -> u64 {
+    unsafe { TSC_HZ }
+}
+
+/// Nanoseconds on the monotonic clock, which starts with the first PIT
+/// interrupt. The TSC fills in the time since the last tick.
+pub fn now() -> u64 {
+    let (ticks, tick_tsc) = unsafe { (TICKS, TICK_TSC) };
+    let tick = ticks_to_nanos(ticks);
+    if tsc_hz() == 0 {
+        return tick;
+    }
+
+    let since_tick = tsc_to_nanos(rdtsc().saturating_sub(tick_tsc));
+    // the tick interrupt may be late, the clock must not run ahead of it
+    tick + since_tick.min(ticks_to_nanos(1) - 1)
+}
+
+/// Nanoseconds since 1970-01-01 00:00:00 UTC, 0 is the epoch until the wall
+/// clock is set
+pub fn realtime() -> u64 {
+    unsafe { REALTIME_OFFSET + now() }
+}
+
+/// Sets the wall clock to `unix_secs` seconds since the epoch
+pub fn set_realtime(unix_secs: u64) {
+    let offset = (unix_secs * NANOS_PER_SEC).saturating_sub(now());
+    unsafe { REALTIME_OFFSET = offset };
+}
+
+/// Busy waits for `nanos` nanoseconds, works with interrupts disabled
+pub fn delay(nanos: u64) {
+    debug_assert!(tsc_hz() != 0, "The TSC is not calibrated");
+    let start = rdtsc();
+    while tsc_to_nanos(rdtsc() - start) < nanos {}
+}
+
+/// Calls `callback` from the timer interrupt once `deadline` is reached
+pub fn add_timer(deadline: Deadline, callback: impl FnOnce() + 'static) {
+    let id = unsafe {
+        NEXT_TIMER += 1;
+        NEXT_TIMER
+    };
+    TIMERS
+        .borrow_mut()
+        .insert((deadline.nanos(), id), Box::new(callback));
+}
+
+/// PIT interrupt, advances the clock before the scheduler sees the tick
+pub fn timer_interrupt(ctx: &mut InterruptContext) {
+    unsafe {
+        TICKS += 1;
+        TICK_TSC = rdtsc();
+    }
+    run_timers();
+    process::preempt(ctx);
+}
+
+fn run_timers() {
+    let now = now();
+    loop {
+        let mut timers = TIMERS.borrow_mut();
+        let Some(entry) = timers.first_entry().filter(|entry| entry.key().0 <= now) else {
+            break;
+        };
+        let callback = entry.remove();
+        // the callback may add timers
+        drop(timers);
+        callback();
+    }
+}
+
+fn ticks_to_nanos(ticks: u64) -> u64 {
+    let divisor = pit::divisor(TICK_HZ) as u128;
+    (ticks as u128 * divisor * NANOS_PER_SEC as u128 / MAX_FREQ as u128) as u64
+}
+
+fn tsc_to_nanos(cycles: u64) -> u64 {
+    let hz = tsc_hz();
+    cycles / hz * NANOS_PER_SEC + cycles % hz * NANOS_PER_SEC / hz
+}
@@ -1,12 +1,17 @@
 use core::arch::asm;
+use core::cell::Cell;
 use core::mem;
 
-pub static TSS: Tss = Tss::new(16, 0x7c00);
+use crate::paging::KERNEL_BASE;
+
+/// Interrupts from user space run on the kernel stack of the current process,
+/// the boot stack is only used until the first process starts
+pub static TSS: Tss = Tss::new(16, (KERNEL_BASE + 0x7c00) as u32);
 
 #[repr(C, align(1))]
 pub struct Tss {
     _filler0: u32,
-    pub esp0: u32,
+    esp0: Cell<u32>,
     pub ss0: u16,
     _filler1: [u8; 92],
     pub iomap_base: u16,
@@ -17,7 +22,7 @@ impl Tss {
     pub const fn new(ss0: u16, esp0: u32) -> Tss {
         Tss {
             _filler0: 0,
-            esp0,
+            esp0: Cell::new(esp0),
             ss0,
             _filler1: [0; 92],
             iomap_base: mem::size_of::<Tss>() as u16,
@@ -25,6 +30,10 @@ impl Tss {
         }
     }
 
+    pub fn set_esp0(&self, esp0: u32) {
+        self.esp0.set(esp0);
+    }
+
     pub fn load(&self) {
         unsafe {
             asm!("ltr {0:x}", in(reg) 40);
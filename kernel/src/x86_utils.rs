@@ -69,6 +69,10 @@ pub fn sti() {
     unsafe { asm!("sti") }
 }
 
+pub fn hlt() {
+    unsafe { asm!("hlt") }
+}
+
 pub fn lidt(ptr: *const u8) {
     unsafe { asm!("lidt [{}]", in(reg) ptr) }
 }
@@ -83,15 +87,6 @@ pub fn rdtsc() -> u64 {
     (high as u64) << 32 | (low as u64)
 }
 
-pub fn tsc_sleep(ticks: u64) {
-    let start = rdtsc();
-    let mut cur = start;
-
-    while cur - start < ticks {
-        cur = rdtsc();
-    }
-}
-
 pub fn esp() -> usize {
     let mut esp: usize;
     unsafe { asm!("mov {}, esp", out(reg) esp) }
@@ -15,4 +15,17 @@ pub fn main(args: &[*const u8]) {
         }
         println!("");
     }
+
+    match stdlib::fork() {
+        Ok(0) => {
+            println!("Hello from the child");
+            stdlib::exit(7);
+        }
+        Ok(pid) => println!("Forked child {}", pid),
+        Err(err) => println!("Fork failed: {}", err),
+    }
+
+    while let Ok((pid, status)) = stdlib::wait() {
+        println!("Child {} ended: {:?}", pid, status);
+    }
 }
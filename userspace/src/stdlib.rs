@@ -1,3 +1,6 @@
+// Library of every program, each one uses only a part of it
+#![allow(dead_code)]
+
 use crate::main;
 use core::arch::{asm, naked_asm};
 use core::panic::PanicInfo;
@@ -5,95 +8,298 @@ use core::{ffi, fmt, slice};
 use utils::io::Write;
 
 pub const SYSCALL_EXIT: u32 = 0x1;
+pub const SYSCALL_FORK: u32 = 0x2;
+pub const SYSCALL_READ: u32 = 0x3;
 pub const SYSCALL_WRITE: u32 = 0x4;
+pub const SYSCALL_OPEN: u32 = 0x5;
+pub const SYSCALL_CLOSE: u32 = 0x6;
+pub const SYSCALL_WAITPID: u32 = 0x7;
+pub const SYSCALL_LINK: u32 = 0x9;
+pub const SYSCALL_TIME: u32 = 0xd;
+pub const SYSCALL_UPTIME: u32 = 0xe;
+pub const SYSCALL_SET_TTY: u32 = 0xf;
+pub const SYSCALL_LSEEK: u32 = 0x13;
+pub const SYSCALL_RENAME: u32 = 0x26;
+pub const SYSCALL_MKDIR: u32 = 0x27;
+pub const SYSCALL_RMDIR: u32 = 0x28;
+pub const SYSCALL_GETTIMEOFDAY: u32 = 0x4e;
+pub const SYSCALL_TRUNCATE: u32 = 0x5c;
+pub const SYSCALL_FTRUNCATE: u32 = 0x5d;
+pub const SYSCALL_SETPRIORITY: u32 = 0x61;
+pub const SYSCALL_STAT: u32 = 0x6a;
+pub const SYSCALL_FSTAT: u32 = 0x6c;
+pub const SYSCALL_GETDENTS: u32 = 0x8d;
+pub const SYSCALL_SCHED_YIELD: u32 = 0x9e;
+pub const SYSCALL_NANOSLEEP: u32 = 0xa2;
+pub const SYSCALL_CLOCK_GETTIME: u32 = 0x109;
+pub const SYSCALL_SPAWN: u32 = 0x78;
+pub const SYSCALL_UNLINKAT: u32 = 0x12d;
+
+pub type Pid = u32;
+pub type Fd = u32;
+
+/// Descriptors every process starts with, all of them are its terminal
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+/// open flags, without O_WRONLY or O_RDWR the file is read only
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+/// Reads return 0 instead of waiting for input
+pub const O_NONBLOCK: u32 = 0o4000;
+pub const O_DIRECTORY: u32 = 0o200000;
+
+/// unlinkat directory for relative paths, paths are always absolute
+pub const AT_FDCWD: i32 = -100;
+/// unlinkat flag, remove a directory
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// lseek origins
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// File type bits of `Stat::mode`
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// File types of `DirEntry::kind`
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// waitpid option, return 0 instead of waiting for a child to exit
+pub const WNOHANG: u32 = 1;
+
+/// setpriority target, the only one there is
+pub const PRIO_PROCESS: u32 = 0;
+
+/// Wall clock, seconds since 1970-01-01 00:00:00 UTC
+pub const CLOCK_REALTIME: u32 = 0;
+/// Clock counting from boot, it never jumps
+pub const CLOCK_MONOTONIC: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timespec {
+    pub sec: i32,
+    pub nsec: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timeval {
+    pub sec: i32,
+    pub usec: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stat {
+    pub ino: u32,
+    /// File type and permission bits
+    pub mode: u32,
+    pub nlink: u32,
+    pub size: u32,
+    /// Seconds since 1970-01-01 00:00:00 UTC
+    pub mtime: u32,
+}
+
+/// Directory entry decoded from what getdents stores
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry<'a> {
+    pub ino: u32,
+    /// One of the `DT_*` types
+    pub kind: u8,
+    pub name: &'a [u8],
+}
+
+/// Entries in the first `len` bytes of a getdents buffer
+pub struct DirEntries<'a> {
+    buf: &'a [u8],
+}
+
+/// How a child ended, decoded from its wait status
+#[derive(Debug, Clone, Copy)]
+pub enum ExitStatus {
+    Exited(u32),
+    /// Killed by the kernel: 1 NPE, 2 SOE, 3 UB, 4 out of memory, 5 other
+    Killed(u32),
+}
+
+impl ExitStatus {
+    pub fn from_raw(status: u32) -> Self {
+        match status & 0x7f {
+            0 => Self::Exited((status >> 8) & 0xff),
+            fault => Self::Killed(fault),
+        }
+    }
+}
+
+impl<'a> DirEntries<'a> {
+    pub fn new(buf: &'a [u8], len: usize) -> Self {
+        Self { buf: &buf[..len] }
+    }
+}
+
+impl<'a> Iterator for DirEntries<'a> {
+    type Item = DirEntry<'a>;
+
+    /// Records are the inode number, the offset of the next one, the record
+    /// length, the NUL terminated name and the type in the last byte
+    fn next(&mut self) -> Option<DirEntry<'a>> {
+        let len = u16::from_le_bytes(self.buf.get(8..10)?.try_into().unwrap()) as usize;
+        let record = self.buf.get(..len).filter(|_| len > 10)?;
+        self.buf = &self.buf[len..];
+
+        let name = &record[10..len - 1];
+        Some(DirEntry {
+            ino: u32::from_le_bytes(record[..4].try_into().unwrap()),
+            kind: record[len - 1],
+            name: &name[..name.iter().position(|&c| c == 0)?],
+        })
+    }
+}
 
 #[macro_export]
 macro_rules! syscall {
-    ($x:expr) => {
+    ($x:expr) => {{
+        let eax = $x as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
+                inlateout("eax") eax => ret,
                 options(nostack)
             )
         }
-    };
-    ($x:expr, $y:expr) => {
+        ret
+    }};
+    ($x:expr, $y:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
                 options(nostack)
             )
         }
-    };
-    ($x:expr, $y:expr, $z:expr) => {
+        ret
+    }};
+    ($x:expr, $y:expr, $z:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ecx = $z as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
-                in("ecx") $z as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
+                in("ecx") ecx,
                 options(nostack)
             )
         }
-    };
-    ($x:expr, $y:expr, $z:expr, $w:expr) => {
+        ret
+    }};
+    ($x:expr, $y:expr, $z:expr, $w:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ecx = $z as u32;
+        let edx = $w as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
-                in("ecx") $z as u32,
-                in("edx") $w as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
+                in("ecx") ecx,
+                in("edx") edx,
                 options(nostack)
             )
         }
-    };
-    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr) => {
+        ret
+    }};
+    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ecx = $z as u32;
+        let edx = $w as u32;
+        let esi = $u as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
-                in("ecx") $z as u32,
-                in("edx") $w as u32,
-                in("esi") $u as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
+                in("ecx") ecx,
+                in("edx") edx,
+                in("esi") esi,
                 options(nostack)
             )
         }
-    };
-    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr, $v:expr) => {
+        ret
+    }};
+    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr, $v:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ecx = $z as u32;
+        let edx = $w as u32;
+        let esi = $u as u32;
+        let edi = $v as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
-                in("ecx") $z as u32,
-                in("edx") $w as u32,
-                in("esi") $u as u32,
-                in("edi") $v as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
+                in("ecx") ecx,
+                in("edx") edx,
+                in("esi") esi,
+                in("edi") edi,
                 options(nostack)
             )
         }
-    };
+        ret
+    }};
 
-    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr, $v:expr, $s:expr) => {
+    ($x:expr, $y:expr, $z:expr, $w:expr, $u:expr, $v:expr, $s:expr) => {{
+        let eax = $x as u32;
+        let ebx = $y as u32;
+        let ecx = $z as u32;
+        let edx = $w as u32;
+        let esi = $u as u32;
+        let edi = $v as u32;
+        let ebp = $s as u32;
+        let ret: i32;
         unsafe {
             core::arch::asm!(
                 "int 0x80",
-                in("eax") $x as u32,
-                in("ebx") $y as u32,
-                in("ecx") $z as u32,
-                in("edx") $w as u32,
-                in("esi") $u as u32,
-                in("edi") $v as u32,
-                in("ebp") $s as u32,
+                inlateout("eax") eax => ret,
+                in("ebx") ebx,
+                in("ecx") ecx,
+                in("edx") edx,
+                in("esi") esi,
+                in("edi") edi,
+                in("ebp") ebp,
                 options(nostack)
             )
         }
-    };
+        ret
+    }};
 }
 pub(crate) use syscall;
 
@@ -104,15 +310,208 @@ pub fn exit(code: u32) -> ! {
 }
 
 #[inline(always)]
-pub fn write(buffer: &[u8]) {
-    syscall!(SYSCALL_WRITE, buffer.as_ptr(), buffer.len());
+pub fn write(fd: Fd, buffer: &[u8]) -> Result<usize, i32> {
+    let ret = syscall!(SYSCALL_WRITE, fd, buffer.as_ptr(), buffer.len());
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+/// Reads into `buffer`, returns the number of bytes read and 0 at the end of
+/// the file. The terminal gives key codes on the screen and bytes on a serial
+/// port, it waits for input unless opened with `O_NONBLOCK`.
+#[inline(always)]
+pub fn read(fd: Fd, buffer: &mut [u8]) -> Result<usize, i32> {
+    let ret = syscall!(SYSCALL_READ, fd, buffer.as_mut_ptr(), buffer.len());
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+/// Opens the file at the absolute `path`
+#[inline(always)]
+pub fn open(path: &ffi::CStr, flags: u32) -> Result<Fd, i32> {
+    let ret = syscall!(SYSCALL_OPEN, path.as_ptr(), flags);
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+#[inline(always)]
+pub fn close(fd: Fd) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_CLOSE, fd);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Moves the offset of `fd` relative to `whence`, returns the new offset
+#[inline(always)]
+pub fn lseek(fd: Fd, offset: i32, whence: u32) -> Result<u32, i32> {
+    let ret = syscall!(SYSCALL_LSEEK, fd, offset, whence);
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+#[inline(always)]
+pub fn stat(path: &ffi::CStr) -> Result<Stat, i32> {
+    let mut stat = Stat::default();
+    let ret = syscall!(SYSCALL_STAT, path.as_ptr(), &raw mut stat);
+    if ret < 0 { Err(ret) } else { Ok(stat) }
+}
+
+/// Creates the directory `path`
+#[inline(always)]
+pub fn mkdir(path: &ffi::CStr) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_MKDIR, path.as_ptr(), 0o755);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Removes the empty directory `path`
+#[inline(always)]
+pub fn rmdir(path: &ffi::CStr) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_RMDIR, path.as_ptr());
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Removes the file `path`
+#[inline(always)]
+pub fn unlink(path: &ffi::CStr) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_UNLINKAT, AT_FDCWD, path.as_ptr(), 0);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Gives the file `old` the second name `new`
+#[inline(always)]
+pub fn link(old: &ffi::CStr, new: &ffi::CStr) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_LINK, old.as_ptr(), new.as_ptr());
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Moves the file `old` to `new`, replacing what is there
+#[inline(always)]
+pub fn rename(old: &ffi::CStr, new: &ffi::CStr) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_RENAME, old.as_ptr(), new.as_ptr());
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Cuts the file `path` to `length` bytes or extends it with zeroes
+#[inline(always)]
+pub fn truncate(path: &ffi::CStr, length: u32) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_TRUNCATE, path.as_ptr(), length);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+#[inline(always)]
+pub fn ftruncate(fd: Fd, length: u32) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_FTRUNCATE, fd, length);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+#[inline(always)]
+pub fn fstat(fd: Fd) -> Result<Stat, i32> {
+    let mut stat = Stat::default();
+    let ret = syscall!(SYSCALL_FSTAT, fd, &raw mut stat);
+    if ret < 0 { Err(ret) } else { Ok(stat) }
+}
+
+/// Reads entries of the directory `fd` into `buffer`, returns the bytes used
+/// and 0 after the last entry. `DirEntries` decodes them.
+#[inline(always)]
+pub fn getdents(fd: Fd, buffer: &mut [u8]) -> Result<usize, i32> {
+    let ret = syscall!(SYSCALL_GETDENTS, fd, buffer.as_mut_ptr(), buffer.len());
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+/// Makes the serial port COM`port` the terminal, for this process and the
+/// children it starts afterwards
+#[inline(always)]
+pub fn set_tty(port: u32) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_SET_TTY, port);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+/// Duplicates the process, returns the pid of the child in the parent and 0
+/// in the child
+#[inline(always)]
+pub fn fork() -> Result<Pid, i32> {
+    let ret = syscall!(SYSCALL_FORK);
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+/// Waits for the child `pid` to exit, -1 waits for any child. Returns 0
+/// with `WNOHANG` if no child exited yet.
+#[inline(always)]
+pub fn waitpid(pid: i32, options: u32) -> Result<(Pid, ExitStatus), i32> {
+    let mut status = 0u32;
+    let ret = syscall!(SYSCALL_WAITPID, pid, &raw mut status, options);
+    if ret < 0 {
+        Err(ret)
+    } else {
+        Ok((ret as _, ExitStatus::from_raw(status)))
+    }
+}
+
+#[inline(always)]
+pub fn wait() -> Result<(Pid, ExitStatus), i32> {
+    waitpid(-1, 0)
+}
+
+/// Starts the program at the absolute path `name`, or the boot module `name`,
+/// with NUL terminated `args`, returns its pid or a
+/// negative error code
+#[inline(always)]
+pub fn spawn(name: &ffi::CStr, args: &[*const u8]) -> Result<Pid, i32> {
+    let ret = syscall!(SYSCALL_SPAWN, name.as_ptr(), args.as_ptr(), args.len());
+    if ret < 0 { Err(ret) } else { Ok(ret as _) }
+}
+
+/// Lets another process run
+#[inline(always)]
+pub fn sched_yield() {
+    syscall!(SYSCALL_SCHED_YIELD);
+}
+
+/// Sleeps for at least `sec` seconds and `nsec` nanoseconds
+#[inline(always)]
+pub fn nanosleep(sec: u32, nsec: u32) -> Result<(), i32> {
+    let req = Timespec {
+        sec: sec as _,
+        nsec: nsec as _,
+    };
+    let ret = syscall!(SYSCALL_NANOSLEEP, &raw const req, 0);
+    if ret < 0 { Err(ret) } else { Ok(()) }
+}
+
+#[inline(always)]
+pub fn clock_gettime(clock: u32) -> Result<Timespec, i32> {
+    let mut tp = Timespec { sec: 0, nsec: 0 };
+    let ret = syscall!(SYSCALL_CLOCK_GETTIME, clock, &raw mut tp);
+    if ret < 0 { Err(ret) } else { Ok(tp) }
+}
+
+/// Seconds since 1970-01-01 00:00:00 UTC
+#[inline(always)]
+pub fn time() -> u32 {
+    syscall!(SYSCALL_TIME, 0) as _
+}
+
+#[inline(always)]
+pub fn gettimeofday() -> Result<Timeval, i32> {
+    let mut tv = Timeval { sec: 0, usec: 0 };
+    let ret = syscall!(SYSCALL_GETTIMEOFDAY, &raw mut tv, 0);
+    if ret < 0 { Err(ret) } else { Ok(tv) }
+}
+
+/// Whole seconds since boot
+#[inline(always)]
+pub fn uptime() -> u32 {
+    syscall!(SYSCALL_UPTIME) as _
+}
+
+/// Sets the nice value of the process `pid`, 0 is the caller. Lower values
+/// run first, the range is -20..=19.
+#[inline(always)]
+pub fn setpriority(pid: Pid, prio: i32) -> Result<(), i32> {
+    let ret = syscall!(SYSCALL_SETPRIORITY, PRIO_PROCESS, pid, prio);
+    if ret < 0 { Err(ret) } else { Ok(()) }
 }
 
 pub struct Writer;
 impl Write for Writer {
     fn write(&mut self, buffer: &[u8]) -> utils::io::Result<usize> {
-        write(buffer);
-        Ok(buffer.len())
+        write(STDOUT, buffer).map_err(|_| utils::io::Error::WriteZero)
     }
     fn flush(&mut self) -> utils::io::Result<()> {
         Ok(())
@@ -1,23 +1,17 @@
+#![no_std]
+#![no_main]
+
+mod stdlib;
 use core::arch::asm;
+use stdlib::{O_NONBLOCK, STDIN};
 use utils::{framebuffer, io::Write, key::Key, textbuffer};
 
-pub fn rdtsc() -> u64 {
-    let high: u32;
-    let low: u32;
-
-    unsafe {
-        asm!("rdtsc", out("edx") high, out("eax") low);
-    }
-    (high as u64) << 32 | (low as u64)
+pub fn main(_args: &[*const u8]) {
+    shell();
 }
 
-pub fn tsc_sleep(ticks: u64) {
-    let start = rdtsc();
-    let mut cur = start;
-
-    while cur - start < ticks {
-        cur = rdtsc();
-    }
+fn sleep_ms(ms: u32) {
+    let _ = stdlib::nanosleep(ms / 1000, ms % 1000 * 1_000_000);
 }
 
 fn get_fb() -> framebuffer::Framebuffer {
@@ -40,26 +34,23 @@ fn get_fb() -> framebuffer::Framebuffer {
     }
 }
 
+/// Waits for the next key code on the terminal
 fn get_key() -> u8 {
-    let mut code: i32 = 0;
-    while code == 0 {
-        unsafe {
-            asm!("int 0x80",
-                inout("eax") 3 => code,
-                in("ebx") 0);
-        }
+    let mut key = 0;
+    match stdlib::read(STDIN, core::slice::from_mut(&mut key)) {
+        Ok(1) => key,
+        _ => 0,
     }
-    code as u8
 }
 
-fn get_key_nowait() -> u8 {
-    let mut code: i32 = 0;
-    unsafe {
-        asm!("int 0x80",
-                inout("eax") 3 => code,
-                in("ebx") 0);
+/// Key code of a key pressed already, 0 if there is none. `fd` is the
+/// terminal opened with `O_NONBLOCK`.
+fn get_key_nowait(fd: stdlib::Fd) -> u8 {
+    let mut key = 0;
+    match stdlib::read(fd, core::slice::from_mut(&mut key)) {
+        Ok(1) => key,
+        _ => 0,
     }
-    code as u8
 }
 
 pub fn key_to_symbol(key: Key) -> Option<u8> {
@@ -170,11 +161,11 @@ pub fn key_to_upper_symbol(key: Key) -> Option<u8> {
     }
 }
 
-const SHELL_PROMPT: &'static str = "jttOS> ";
-const COMMAND_CLEAR: &'static [u8] = b"clear";
-const COMMAND_ANIMATION: &'static [u8] = b"color";
+const SHELL_PROMPT: &str = "jttOS> ";
+const COMMAND_CLEAR: &[u8] = b"clear";
+const COMMAND_ANIMATION: &[u8] = b"color";
 
-pub fn entry() {
+fn shell() {
     let fb = get_fb();
     let mut tbw = textbuffer::TextBufferWritter::new(textbuffer::TextBuffer::new(fb));
 
@@ -207,7 +198,7 @@ pub fn entry() {
                 match &command_buf[0..command_index] {
                     COMMAND_CLEAR => tbw.clear(),
                     COMMAND_ANIMATION => animation(&mut tbw),
-                    _ => write!(tbw, "\n").unwrap(),
+                    _ => writeln!(tbw).unwrap(),
                 }
 
                 command_buf = [0; 5];
@@ -243,12 +234,17 @@ pub fn entry() {
 fn animation(tbw: &mut textbuffer::TextBufferWritter) {
     let fb = &tbw.buffer.fb;
 
+    // stdin waits for keys, a second descriptor on the terminal does not
+    let Ok(nowait) = stdlib::open(c"/dev/console", O_NONBLOCK) else {
+        return;
+    };
+
     let mut hue: f32 = 0.0;
     let saturation: f32 = 1.0;
     let value: f32 = 1.0;
 
     loop {
-        let keycode = get_key_nowait();
+        let keycode = get_key_nowait(nowait);
         if keycode != 0 {
             break;
         }
@@ -283,8 +279,9 @@ fn animation(tbw: &mut textbuffer::TextBufferWritter) {
             hue = 0.0;
         }
 
-        tsc_sleep(1000000);
+        sleep_ms(10);
     }
 
+    let _ = stdlib::close(nowait);
     tbw.clear();
 }
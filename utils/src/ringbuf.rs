@@ -48,4 +48,8 @@ impl<T, const N: usize> Ringbuf<T, N> {
     pub const fn is_empty(&self) -> bool {
         self.count == 0
     }
+
+    pub const fn is_full(&self) -> bool {
+        self.count == N
+    }
 }